@@ -0,0 +1,222 @@
+#![allow(dead_code)]
+
+use anyhow::{anyhow, Result};
+use crossbeam::channel::{unbounded, Receiver};
+use fnv::FnvHashMap;
+
+use crate::clutch::{ClutchKey, ClutchMeta, ClutchStore};
+use crate::metric::Metric;
+
+/*
+Threshold alarms, evaluated a period at a time.
+
+A rule raises for a key once its metric has been past the raise level for `periods`
+back to back periods of that key, and clears the first period it is back on the other
+side of the clear level.  Keeping the clear level short of the raise level stops a value
+hovering at the threshold from flapping.  A period with no value for the metric, or a
+gap between periods, starts the count over but leaves a raised alarm raised.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    /// alarm on values above `raise`, clear at or below `clear`
+    Above,
+    /// alarm on values below `raise`, clear at or above `clear`
+    Below,
+}
+
+#[derive(Debug, Clone)]
+pub struct AlarmRule {
+    pub name: String,
+    pub group: u16,
+    pub metric: Metric,
+    pub direction: Direction,
+    pub raise: f64,
+    pub clear: f64,
+    /// consecutive periods past `raise` before raising
+    pub periods: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AlarmState {
+    Raised,
+    Cleared,
+}
+
+#[derive(Debug, Clone)]
+pub struct AlarmEvent {
+    pub rule: String,
+    pub key: ClutchKey,
+    pub value: f64,
+    pub state: AlarmState,
+}
+
+/// Called with every alarm raised or cleared.
+pub type AlarmFn = Box<dyn FnMut(&AlarmEvent) + Send>;
+
+#[derive(Debug, Default)]
+struct KeyState {
+    streak: u32,
+    /// end (time + dur) of the last period seen
+    next: u64,
+    raised: bool,
+}
+
+pub struct AlarmEngine {
+    rules: Vec<AlarmRule>,
+    /// per rule, state by clutch keys
+    state: Vec<FnvHashMap<String, KeyState>>,
+    on_event: Option<AlarmFn>,
+}
+
+impl AlarmRule {
+    fn breached(&self, v: f64) -> bool {
+        match self.direction {
+            Direction::Above => v > self.raise,
+            Direction::Below => v < self.raise,
+        }
+    }
+
+    fn cleared(&self, v: f64) -> bool {
+        match self.direction {
+            Direction::Above => v <= self.clear,
+            Direction::Below => v >= self.clear,
+        }
+    }
+}
+
+impl AlarmEngine {
+    pub fn new() -> AlarmEngine {
+        AlarmEngine { rules: vec![], state: vec![], on_event: None }
+    }
+
+    /// Adds a rule after checking it against its group.
+    pub fn add_rule(&mut self, cm: &ClutchMeta, rule: AlarmRule) -> Result<()> {
+        let group = cm.get_group(rule.group).ok_or_else(|| anyhow!("no group with index {}", rule.group))?;
+        rule.metric.check(group)?;
+        let wrong_side = match rule.direction {
+            Direction::Above => rule.clear > rule.raise,
+            Direction::Below => rule.clear < rule.raise,
+        };
+        if wrong_side {
+            return Err(anyhow!("alarm {} clears at {} which is past its raise level {}", &rule.name, rule.clear, rule.raise));
+        }
+        self.rules.push(rule);
+        self.state.push(FnvHashMap::default());
+        Ok(())
+    }
+
+    pub fn on_event<F>(&mut self, f: F)
+        where F: 'static + Send + FnMut(&AlarmEvent)
+    {
+        self.on_event = Some(Box::new(f));
+    }
+
+    /// Sends every event down a channel instead of a callback.
+    pub fn channel(&mut self) -> Receiver<AlarmEvent> {
+        let (tx, rx) = unbounded();
+        self.on_event(move |e| { let _ = tx.send(e.clone()); });
+        rx
+    }
+
+    /// Evaluates every rule on the clutches of the period starting at `time`, returns the
+    /// events it produced (which also went to the callback).  Periods of a key have to be
+    /// closed in time order, which calling this from a `ClutchStore::on_close` hook gives.
+    pub fn close_period(&mut self, cm: &ClutchMeta, cs: &ClutchStore, time: u64) -> Vec<AlarmEvent> {
+        let mut events = vec![];
+        for (rule, state) in self.rules.iter().zip(self.state.iter_mut()) {
+            let group = match cm.get_group(rule.group) {
+                Some(g) => g,
+                None => continue,
+            };
+            for (ck, cd) in cs.iter().filter(|(ck, _)| ck.groupidx == rule.group && ck.time() == time) {
+                let st = state.entry(ck.keys().to_string()).or_default();
+                if st.next != ck.time() {
+                    st.streak = 0;
+                }
+                st.next = ck.time() + ck.dur() as u64;
+                let v = match rule.metric.eval(group, cd) {
+                    Some(v) => v,
+                    None => {
+                        st.streak = 0;
+                        continue;
+                    }
+                };
+                st.streak = if rule.breached(v) { st.streak + 1 } else { 0 };
+                let state = if !st.raised && st.streak >= rule.periods.max(1) {
+                    st.raised = true;
+                    AlarmState::Raised
+                } else if st.raised && rule.cleared(v) {
+                    st.raised = false;
+                    AlarmState::Cleared
+                } else {
+                    continue;
+                };
+                events.push(AlarmEvent { rule: rule.name.clone(), key: ck.clone(), value: v, state });
+            }
+        }
+        if let Some(f) = self.on_event.as_mut() {
+            for e in events.iter() {
+                f(e);
+            }
+        }
+        events
+    }
+
+    /// (rule name, clutch keys) of every alarm currently raised
+    pub fn active(&self) -> Vec<(&str, &str)> {
+        let mut v: Vec<(&str, &str)> = self.rules.iter().zip(self.state.iter())
+            .flat_map(|(r, st)| st.iter().filter(|(_, s)| s.raised).map(move |(k, _)| (r.name.as_str(), k.as_str())))
+            .collect();
+        v.sort_unstable();
+        v
+    }
+}
+
+#[test]
+fn test_alarms() {
+    let mut cm = ClutchMeta::new();
+    let mut cs = ClutchStore::new();
+    let g = cm.find_or_new_group("cells");
+    // OM 1001 per period for two cells, OM 1/2 a success ratio
+    let load: [(u32, u32); 7] = [(600, 100), (700, 100), (800, 100), (450, 100), (300, 100), (900, 100), (600, 100)];
+    for (p, (a, b)) in load.iter().enumerate() {
+        for (cell, v) in [("a", *a), ("b", *b)] {
+            let key = ClutchKey::new(g.idx, cell.to_string(), p as u64 * 900, 900, 0);
+            let cd = cs.find_or_add_clutchdata(g, &key);
+            cd.add_om_u32(false, g, 1001, v).unwrap();
+            cd.add_om_u32(false, g, 1, if cell == "b" && p == 2 { 90 } else { 99 }).unwrap();
+            cd.add_om_u32(false, g, 2, 100).unwrap();
+        }
+    }
+    let gi = g.idx;
+
+    let mut ae = AlarmEngine::new();
+    let rule = AlarmRule { name: "load".to_string(), group: gi, metric: Metric::Om(1001),
+                           direction: Direction::Above, raise: 500.0, clear: 400.0, periods: 3 };
+    assert!(ae.add_rule(&cm, AlarmRule { clear: 600.0, ..rule.clone() }).is_err());
+    assert!(ae.add_rule(&cm, AlarmRule { metric: Metric::Om(77), ..rule.clone() }).is_err());
+    ae.add_rule(&cm, rule).unwrap();
+    ae.add_rule(&cm, AlarmRule { name: "success".to_string(), group: gi, metric: Metric::Ratio { num: 1, den: 2 },
+                                 direction: Direction::Below, raise: 0.95, clear: 0.98, periods: 1 }).unwrap();
+    let rx = ae.channel();
+
+    let mut got = vec![];
+    for p in 0..7u64 {
+        for e in ae.close_period(&cm, &cs, p * 900) {
+            got.push(format!("{} {} {} {} {:?}", p, e.rule, e.key.keys(), e.value, e.state));
+        }
+        if p == 2 {
+            assert_eq!(ae.active(), vec![("load", "a"), ("success", "b")]);
+        }
+    }
+    assert_eq!(got, vec![
+        "2 load a 800 Raised",
+        "2 success b 0.9 Raised",
+        "3 success b 0.99 Cleared",
+        // 450 is under the raise level but not down to the clear level
+        "4 load a 300 Cleared",
+    ]);
+    assert_eq!(rx.try_iter().count(), 4);
+    assert!(ae.active().is_empty());
+}
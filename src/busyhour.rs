@@ -0,0 +1,159 @@
+#![allow(dead_code)]
+
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, Result};
+use fnv::FnvHashMap;
+
+use crate::clutch::{ClutchData, ClutchKey, ClutchMeta, ClutchStore, OmType, OmValue};
+use crate::metric::Metric;
+use crate::rollup::{rollup_store, Agg, RollupOpts, Span, TzRule};
+
+/*
+Busy hour per key and local day.
+
+Source clutches are first rolled up to local clock hours (a no-op regrouping for hourly
+data, sums for 15 minute data, see `rollup`), then each day keeps the hour whose metric is
+highest, the earliest on ties.  The day's clutch holds every OM of that hour as rolled
+up, with its quality and suspect flags, plus when the hour starts as seconds into the
+day - a u32 whatever the date, and on DST days the real elapsed time, not the clock hour.
+*/
+
+#[derive(Debug, Clone)]
+pub struct BusyHourOpts {
+    pub tz: TzRule,
+    /// what makes an hour busy
+    pub metric: Metric,
+    /// group of the daily clutches
+    pub target: u16,
+    /// OM the busy hour's start goes to, as u32 seconds from the start of the day
+    pub hour_id: u32,
+    /// how OMs combine into hours, OMs not listed are summed
+    pub aggs: FnvHashMap<u32, Agg>,
+}
+
+/// The busy hour of every key and day of group `src` as a new store of daily clutches.
+/// Days where no hour has a value for the metric get no clutch.
+pub fn busy_hour_store(cm: &mut ClutchMeta, cs: &ClutchStore, src: u16, opts: &BusyHourOpts) -> Result<ClutchStore> {
+    let sg = cm.get_group(src).ok_or_else(|| anyhow!("no group with index {}", src))?;
+    opts.metric.check(sg)?;
+    if sg.om_map.contains_key(&opts.hour_id) {
+        return Err(anyhow!("busy hour OM id: {} is already an OM of group {}", opts.hour_id, &sg.group));
+    }
+    let tg = cm.get_group(opts.target).ok_or_else(|| anyhow!("no group with index {}", opts.target))?;
+    if tg.om_map.get(&opts.hour_id).is_some_and(|m| !matches!(m.kind, OmType::TypeU32)) {
+        return Err(anyhow!("busy hour OM id: {} is not u32 in group {}", opts.hour_id, &tg.group));
+    }
+    let hourly = RollupOpts { tz: opts.tz.clone(), span: Span::Hour, target: src, aggs: opts.aggs.clone() };
+    let hours = rollup_store(cm, cs, src, &hourly)?;
+    let daily = RollupOpts { span: Span::Day, ..hourly };
+
+    // the busy hour's OMs keep their kind in the target, checked once the hourly rollup set it
+    let sg = cm.get_group(src).unwrap();
+    let tg = cm.get_group(opts.target).unwrap();
+    for m in sg.om_map.values().filter(|m| matches!(m.kind, OmType::TypeU32 | OmType::TypeF64)) {
+        if let Some(t) = tg.om_map.get(&m.id) {
+            if std::mem::discriminant(&t.kind) != std::mem::discriminant(&m.kind) {
+                return Err(anyhow!("OM id: {} is {} but {} in group {}", m.id, &m.kind, &t.kind, &tg.group));
+            }
+        }
+    }
+
+    // by keys and day start: the day's end, the busiest hour so far and its metric
+    let mut days: BTreeMap<(&str, i64), (i64, &ClutchKey, &ClutchData, f64)> = BTreeMap::new();
+    for (ck, cd) in hours.iter() {
+        let v = match opts.metric.eval(sg, cd) {
+            Some(v) => v,
+            None => continue,
+        };
+        let (start, end) = daily.period(ck.time() as i64);
+        // hours come in time order, so only a higher value takes over
+        let e = days.entry((ck.keys(), start)).or_insert((end, ck, cd, v));
+        if v > e.3 {
+            *e = (end, ck, cd, v);
+        }
+    }
+    let busy: Vec<_> = days.into_iter()
+        .map(|((keys, start), (end, hk, hd, _))| {
+            let oms: Vec<(u32, OmValue, OmType, u8)> = hd.oms(sg)
+                .map(|(id, v)| (id, v, sg.om_map[&id].kind.clone(), hd.quality(id)))
+                .collect();
+            (ClutchKey::new(opts.target, keys.to_string(), start as u64, (end - start) as u32, opts.tz.offset_at(start)),
+             (hk.time() as i64 - start) as u32, hd.suspect(), oms)
+        })
+        .collect();
+
+    let group = cm.get_group_by_idx(opts.target).unwrap();
+    let mut out = ClutchStore::new();
+    for (key, hour, suspect, oms) in busy {
+        let cd = out.find_or_add_clutchdata(group, &key);
+        if let Some(reason) = suspect {
+            cd.set_suspect(reason);
+        }
+        for (id, v, kind, q) in oms {
+            match v {
+                OmValue::U32(v) => cd.add_om_u32(true, group, id, v)?,
+                OmValue::F64(v) => cd.add_om_f64(true, group, id, v)?,
+                OmValue::NULL => cd.set_null(true, group, id, &kind)?,
+                _ => continue,
+            }
+            cd.add_quality(id, q);
+        }
+        cd.add_om_u32(true, group, opts.hour_id, hour)?;
+    }
+    Ok(out)
+}
+
+#[test]
+fn test_busy_hour() {
+    let mut cm = ClutchMeta::new();
+    let mut cs = ClutchStore::new();
+    let g = cm.find_or_new_group("quarters");
+    let tz = TzRule::fixed(3600);
+    // two local days from 1970-01-02T00:00+01:00 of 15 minute traffic (OM 1) and
+    // attempts (OM 2), cell1 peaking at 09:00 then 17:00, cell2 flat
+    let day0 = 86400 - 3600;
+    for q in 0..2 * 96u64 {
+        let t = day0 + q * 900;
+        let hour = (q / 4) % 24;
+        for cell in ["cell1", "cell2"] {
+            let key = ClutchKey::new(g.idx, cell.to_string(), t, 900, 3600);
+            let cd = cs.find_or_add_clutchdata(g, &key);
+            let peak = if q < 96 { 9 } else { 17 };
+            let traffic = if cell == "cell1" && hour == peak { 50 } else { 10 };
+            cd.add_om_u32(false, g, 1, traffic).unwrap();
+            cd.add_om_u32(false, g, 2, traffic * 2 + hour as u32).unwrap();
+        }
+    }
+    let gi = g.idx;
+    let bh = cm.find_or_new_group("busy").idx;
+    let mut opts = BusyHourOpts { tz, metric: Metric::Om(1), target: bh, hour_id: 900, aggs: FnvHashMap::default() };
+    let out = busy_hour_store(&mut cm, &cs, gi, &opts).unwrap();
+    let g = cm.get_group_by_name("busy").unwrap();
+    let got = out.iter()
+        .map(|(k, cd)| format!("{} {} {} {} {}", k.keys(), k.start_iso(), cd.get_u32(g, 900).unwrap().unwrap(),
+                               cd.get_u32(g, 1).unwrap().unwrap(), cd.get_u32(g, 2).unwrap().unwrap()))
+        .collect::<Vec<_>>();
+    assert_eq!(got, vec![
+        "cell1 1970-01-02T00:00:00+01:00 32400 200 436",
+        "cell1 1970-01-03T00:00:00+01:00 61200 200 468",
+        // flat all day, the first hour wins
+        "cell2 1970-01-02T00:00:00+01:00 0 40 80",
+        "cell2 1970-01-03T00:00:00+01:00 0 40 80",
+    ]);
+
+    opts.hour_id = 1;
+    assert!(busy_hour_store(&mut cm, &cs, gi, &opts).is_err());
+    opts.hour_id = 900;
+    opts.metric = Metric::Ratio { num: 1, den: 3 };
+    assert!(busy_hour_store(&mut cm, &cs, gi, &opts).is_err());
+
+    // an OM copied over has to be of the same kind in the target
+    opts.metric = Metric::Om(1);
+    let other = cm.find_or_new_group("busy_f64");
+    other.find_setup_meta_slot(2, &OmType::TypeF64);
+    opts.target = other.idx;
+    let err = busy_hour_store(&mut cm, &cs, gi, &opts).unwrap_err();
+    assert_eq!(err.to_string(), "OM id: 2 is u32 but f64 in group busy_f64");
+    assert_eq!(cm.get_group_by_name("busy_f64").unwrap().om_map.len(), 1);
+}
@@ -1,3 +1,5 @@
+use std::path::PathBuf;
+
 use anyhow::{anyhow, Context, Result};
 use structopt::StructOpt;
 
@@ -67,6 +69,114 @@ pub struct Cli {
     /// every N OM (k3 mod N) will be null, 0 = never
     pub random_nulls: u32,
 
+    #[structopt(long)]
+    /// keep OM values in per-group slabs instead of a ClutchData per clutch
+    pub slab: bool,
+
+    #[structopt(long, parse(from_os_str))]
+    /// write the clutches of thread 0's last iteration to this export file
+    pub export: Option<PathBuf>,
+
+    #[structopt(subcommand)]
+    /// tools over export files instead of running the test
+    pub cmd: Option<Command>,
+}
+
+#[derive(StructOpt, Debug, Clone)]
+pub enum Command {
+    /// Compare two export files: clutches only in one of them and OMs that differ
+    Diff {
+        #[structopt(parse(from_os_str))]
+        a: PathBuf,
+        #[structopt(parse(from_os_str))]
+        b: PathBuf,
+
+        #[structopt(long, default_value("0"))]
+        /// f64 values this close are the same
+        abs_tol: f64,
+
+        #[structopt(long, default_value("1e-9"))]
+        /// f64 values this close relative to the larger are the same
+        rel_tol: f64,
+    },
+
+    /// Clutches of a group with the largest (or smallest) values of an OM
+    Top {
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+
+        #[structopt(short, long)]
+        /// group name
+        group: String,
+
+        #[structopt(long)]
+        /// OM id to rank by
+        id: u32,
+
+        #[structopt(short, default_value("20"))]
+        /// how many clutches to list
+        n: usize,
+
+        #[structopt(long)]
+        /// smallest values first
+        bottom: bool,
+
+        #[structopt(long, default_value("0"))]
+        /// first clutch time (epoch secs) included
+        from: u64,
+
+        #[structopt(long)]
+        /// clutch times from here on are left out
+        to: Option<u64>,
+    },
+
+    /// count/sum/mean/stddev/min/max and percentiles of an OM across clutches
+    Stats {
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+
+        #[structopt(short, long)]
+        /// group name
+        group: String,
+
+        #[structopt(long)]
+        /// OM id
+        id: u32,
+
+        #[structopt(long, default_value("0"))]
+        /// first clutch time (epoch secs) included
+        from: u64,
+
+        #[structopt(long)]
+        /// clutch times from here on are left out
+        to: Option<u64>,
+
+        #[structopt(long, default_value("0.01"))]
+        /// relative accuracy of the percentiles
+        accuracy: f64,
+
+        #[structopt(long)]
+        /// also print the sketch buckets as a histogram
+        histogram: bool,
+    },
+
+    /// Keys reported against those expected and OMs set/NULL, per period of a group
+    Completeness {
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+
+        #[structopt(short, long)]
+        /// group name
+        group: String,
+
+        #[structopt(long, parse(from_os_str))]
+        /// expected keys, one per line - otherwise keys of earlier periods are expected
+        inventory: Option<PathBuf>,
+
+        #[structopt(long)]
+        /// also list the missing and unexpected keys
+        keys: bool,
+    },
 }
 
 fn parse_types_list(str: &str) -> Result<u32> {
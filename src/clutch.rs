@@ -2,7 +2,8 @@
 #![allow(unused_imports)]
 
 use std::cmp::{Ordering, max};
-use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::error::Error;
 use std::fmt::Display;
 use std::fmt::Formatter;
@@ -11,12 +12,18 @@ use bit_vec::BitVec;
 use anyhow::{anyhow, Result};
 use snafu::Backtrace;
 use fnv::FnvHashMap;
+use chrono::{DateTime, FixedOffset, Offset, SecondsFormat, TimeZone, Utc};
 
 //use crate::bitset::BitSet;
 use crate::clutch::OmType::{TypeF64, TypeU32};
 
 pub const RESIZE_INC: usize = 8usize;
 
+/// Fill ratio below which a clutch keeps its OMs as sorted pairs instead of by slot.
+pub const DEFAULT_SPARSE_RATIO: f32 = 0.25;
+/// Groups with fewer slots than this always stay dense - not worth the lookups.
+pub const SPARSE_MIN_SLOTS: usize = 64;
+
 type GroupIdx = u16;
 
 #[derive(Debug, Clone)]
@@ -35,17 +42,23 @@ impl std::fmt::Display for OmType {
         let s = match self {
             TypeF64 => "f64",
             TypeU32 => "u32",
-            String => "str",
-            _ => "unknown",
+            OmType::TypeI32 => "i32",
+            OmType::TypeU64 => "u64",
+            OmType::TypeI64 => "i64",
+            OmType::TypeF32 => "f32",
+            OmType::TypeString => "str",
         };
         write!(f,"{}",  &s)
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum OmValue {
     NoMeta,
+    /// the OM was reported, as null
     NULL,
+    /// the OM is known to the group but this clutch never got it
+    Unset,
     U32(u32),
     F64(f64),
     String(String),
@@ -56,6 +69,7 @@ impl std::fmt::Display for OmValue {
         match self {
             OmValue::NoMeta => write!(f, "NO META"),
             OmValue::NULL => write!(f, "NULL"),
+            OmValue::Unset => write!(f, "UNSET"),
             OmValue::F64(v) => write!(f, "{}", v),
             OmValue::U32(v) => write!(f, "{}", v),
             OmValue::String(s) => write!(f, "{}", s),
@@ -65,7 +79,7 @@ impl std::fmt::Display for OmValue {
 }
 
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct OmMeta {
     pub kind: OmType,
     pub id: u32,
@@ -80,8 +94,23 @@ pub struct OmGroup {
     pub om32_slots: usize,
     pub om64_slots: usize,
     pub omstr_slots: usize,
+    /// see `DEFAULT_SPARSE_RATIO` - 0 keeps every clutch dense
+    pub sparse_ratio: f32,
+    /// how long after the end of its period (time + dur) a clutch is kept, None is forever
+    pub retention: Option<std::time::Duration>,
+    /// record the ingest time of every OM write, not just per clutch
+    pub track_om_ingest: bool,
+    /// prior values kept per OM when overwritten, 0 keeps none
+    pub history_depth: usize,
+    /// keys expected every period, checked as periods close - set it before adding clutches,
+    /// only the keys added while it is set are indexed for the check
+    pub inventory: Option<BTreeSet<String>>,
+    /// at period close, add an empty clutch marked `Missing` for each inventory key without one
+    pub fill_missing: bool,
 }
 
+/// `time` is the start of the period in seconds since the Unix epoch (UTC), `dur` its
+/// length in seconds and `offset` the reporting element's UTC offset in seconds east.
 #[derive(Debug, Eq, Clone)]
 pub struct ClutchKey {
     pub groupidx: u16,
@@ -92,14 +121,133 @@ pub struct ClutchKey {
 }
 
 
+/// Anything that can take OM values for a single clutch.
+///
+/// Lets writers like the benchmark fill either a `ClutchData` or a slab row
+/// without caring which storage mode is in use.
+pub trait OmSink {
+    fn add_om_u32(&mut self, overwrite: bool, group: &mut OmGroup, id: u32, val: u32) -> Result<()>;
+    fn add_om_f64(&mut self, overwrite: bool, group: &mut OmGroup, id: u32, val: f64) -> Result<()>;
+    fn set_null(&mut self, overwrite: bool, group: &mut OmGroup, id: u32, kind: &OmType) -> Result<()>;
+}
+
+/// Values of one slot width for a clutch.  Dense keeps a set bit and a value per slot
+/// of the group, sparse keeps only the set slots as sorted (slot, value) pairs.
+#[derive(Debug)]
+enum OmSlots<T> {
+    Dense { set: BitVec, vals: Vec<T> },
+    Sparse(Vec<(u32, T)>),
+}
+
+/// Why a whole clutch should not be fully trusted, as marked in the PM source or
+/// inherited from the clutches it was derived from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SuspectReason {
+    /// collection for the period did not complete
+    Incomplete,
+    /// the element's clock changed during the period
+    ClockChange,
+    /// derived from one or more suspect clutches
+    Propagated,
+    /// placeholder for an inventory key that never reported, see `OmGroup::fill_missing`
+    Missing,
+    /// placeholder for a key that reported after its period closed, into the late store
+    Late,
+    Other(u16),
+}
+
+impl SuspectReason {
+    pub fn code(&self) -> u16 {
+        match self {
+            SuspectReason::Incomplete => 1,
+            SuspectReason::ClockChange => 2,
+            SuspectReason::Propagated => 3,
+            SuspectReason::Missing => 4,
+            SuspectReason::Late => 5,
+            SuspectReason::Other(c) => *c,
+        }
+    }
+
+    pub fn from_code(code: u16) -> SuspectReason {
+        match code {
+            1 => SuspectReason::Incomplete,
+            2 => SuspectReason::ClockChange,
+            3 => SuspectReason::Propagated,
+            4 => SuspectReason::Missing,
+            5 => SuspectReason::Late,
+            c => SuspectReason::Other(c),
+        }
+    }
+
+    /// what a clutch derived from one with this reason gets
+    pub fn propagate(&self) -> SuspectReason {
+        SuspectReason::Propagated
+    }
+}
+
+/// Per-OM quality flag bits.  Flags from clutches merged or derived together are OR-ed.
+pub const QUALITY_SUSPECT: u8 = 1;
+pub const QUALITY_ESTIMATED: u8 = 2;
+pub const QUALITY_CAPPED: u8 = 4;
+
 #[derive(Debug)]
 pub struct ClutchData {
-    om_null32: BitVec,
-    om_null64: BitVec,
+    om32: OmSlots<u32>,
+    om64: OmSlots<u64>,
+    suspect: Option<SuspectReason>,
+    /// the store's ingest time when it last handed out this clutch, what writes get stamped with
+    writing_at: u64,
+    /// allocated on first use, most clutches only ever hold numbers
+    x: Option<Box<ClutchExtras>>,
+}
 
-    om32: Vec<u32>,
-    om64: Vec<u64>,
+/// What a clutch holds beyond its u32/f64 values.
+#[derive(Debug, Default)]
+struct ClutchExtras {
     om_str: Vec<(u32, String)>,
+    /// sorted slots reported as explicit NULLs - never set in om32/om64 at the same time
+    null32: Vec<u32>,
+    null64: Vec<u32>,
+    /// sorted (OM id, quality bits), only OMs with flags are here
+    quality: Vec<(u32, u8)>,
+    /// ingest times (epoch secs) of the first and latest write, 0 when not tracked
+    ingested: u64,
+    updated: u64,
+    /// sorted (OM id, ingest time) of the latest write of each OM, for groups that track it
+    om_times: Vec<(u32, u64)>,
+    /// overwritten values by OM id, oldest first within an id, for groups keeping history
+    history: Vec<(u32, OmVersion)>,
+}
+
+static NO_EXTRAS: ClutchExtras = ClutchExtras {
+    om_str: Vec::new(),
+    null32: Vec::new(),
+    null64: Vec::new(),
+    quality: Vec::new(),
+    ingested: 0,
+    updated: 0,
+    om_times: Vec::new(),
+    history: Vec::new(),
+};
+
+/// A value an OM held before an overwrite replaced it.
+#[derive(Debug, Clone)]
+pub struct OmVersion {
+    pub value: OmValue,
+    /// ingest time it was written at, 0 if the group did not track OM ingest times
+    pub written: u64,
+    /// ingest time of the write that replaced it, 0 if not tracked
+    pub replaced: u64,
+}
+
+fn same_value(a: &OmValue, b: &OmValue) -> bool {
+    match (a, b) {
+        (OmValue::U32(a), OmValue::U32(b)) => a == b,
+        (OmValue::F64(a), OmValue::F64(b)) => a.to_bits() == b.to_bits(),
+        (OmValue::String(a), OmValue::String(b)) => a == b,
+        (OmValue::NULL, OmValue::NULL) => true,
+        _ => false,
+    }
 }
 
 /*
@@ -156,9 +304,113 @@ pub struct ClutchMeta {
     group_map: BTreeMap<String, GroupIdx>,
 }
 
-#[derive(Debug)]
+/// Limit on what a `ClutchStore` may hold before its oldest time periods are evicted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Budget {
+    Unlimited,
+    Clutches(usize),
+    /// bytes of keys and values as counted by `ClutchData::memory_usage`
+    Bytes(usize),
+}
+
+/// Called with the time and clutches of each evicted period before they are dropped.
+pub type EvictFn = Box<dyn FnMut(u64, Vec<(ClutchKey, ClutchData)>) + Send>;
+
+/// Called with the time of each period as it closes, while its clutches are still in the store.
+pub type CloseFn = Box<dyn FnMut(&ClutchMeta, &ClutchStore, u64) + Send>;
+
+/// What `ClutchStore::write_clutchdata` does with OMs for a period already closed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LatePolicy {
+    /// write them to the closed period anyway
+    Accept,
+    /// refuse them with an error
+    Reject,
+    /// write them to a separate store, see `ClutchStore::take_late`
+    SideChannel,
+}
+
+/// Open/closed state of the periods of a store.
+struct Lifecycle {
+    grace: std::time::Duration,
+    late: LatePolicy,
+    closed: BTreeSet<u64>,
+    /// latest `now` given to `close_due` - periods ending a grace before it are closed
+    /// even if they never had a clutch
+    watermark: Option<u64>,
+    /// longest dur of each open period
+    durs: BTreeMap<u64, u32>,
+    hooks: Vec<CloseFn>,
+    late_store: Option<Box<ClutchStore>>,
+    /// writes to closed periods by group index
+    late_counts: Vec<u64>,
+    /// inventory checks of the periods closed, for groups with an inventory
+    key_checks: Vec<KeyCheck>,
+    /// keys of each open period by (group, time), for groups that had an inventory when
+    /// the clutches were added - what the inventory check of a closing period looks at
+    period_keys: BTreeMap<(u16, u64), BTreeSet<ClutchKey>>,
+}
+
+/// Keys of a group's inventory without a clutch in a closed period, and keys with one
+/// that are not in it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyCheck {
+    pub group: u16,
+    pub time: u64,
+    pub missing: Vec<String>,
+    pub unexpected: Vec<String>,
+}
+
 pub struct ClutchStore {
     clutches: BTreeMap<ClutchKey, ClutchData>,
+    /// clutch count per time period - keys sort before time so this is the only cheap
+    /// way to find the oldest periods
+    periods: BTreeMap<u64, usize>,
+    budget: Budget,
+    /// running `data_bytes`, kept only while the budget is `Budget::Bytes`
+    bytes: usize,
+    /// the clutch last handed out under a byte budget and its bytes then - it may have
+    /// grown since, which is settled before the count is next used
+    writing: Option<(ClutchKey, usize)>,
+    /// times the store was left over its budget with nothing left to evict
+    overflows: usize,
+    on_evict: Option<EvictFn>,
+    life: Lifecycle,
+    /// stamped on clutches as they are written, 0 is not tracked
+    ingest_time: u64,
+}
+
+impl std::fmt::Debug for ClutchStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("ClutchStore")
+            .field("clutches", &self.clutches)
+            .field("periods", &self.periods)
+            .field("budget", &self.budget)
+            .field("closed", &self.life.closed)
+            .finish()
+    }
+}
+
+/// Heap and struct bytes held by clutches, split by what they hold.
+///
+/// `value_bytes` and `null_bitmap_bytes` only count storage that is in use, anything
+/// allocated but not holding a set OM (unset dense slots, spare Vec capacity) is in
+/// `wasted_bytes` - that is the number to watch when tuning `RESIZE_INC`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MemUsage {
+    pub clutches: usize,
+    pub key_bytes: usize,
+    pub value_bytes: usize,
+    pub null_bitmap_bytes: usize,
+    pub meta_bytes: usize,
+    pub wasted_bytes: usize,
+}
+
+/// `MemUsage` for a whole store plus the break down by group index.
+#[derive(Debug, Default, Clone)]
+pub struct StoreMemUsage {
+    pub total: MemUsage,
+    pub groups: Vec<MemUsage>,
 }
 
 
@@ -181,6 +433,74 @@ impl ClutchKey {
         &mut self.keys
     }
 
+    pub fn keys(&self) -> &str {
+        &self.keys
+    }
+
+    pub fn time(&self) -> u64 {
+        self.time
+    }
+
+    pub fn dur(&self) -> u32 {
+        self.dur
+    }
+
+    pub fn offset(&self) -> i32 {
+        self.offset
+    }
+
+    /// A key for the period starting at `start` - in whatever zone, its offset becomes the
+    /// key's - checked to be aligned to `dur`.
+    pub fn at<Tz: TimeZone>(groupidx: u16, keys: String, start: &DateTime<Tz>, dur: chrono::Duration) -> Result<ClutchKey> {
+        let time = u64::try_from(start.timestamp()).map_err(|_| anyhow!("period start {} is before the epoch", start.to_rfc3339()))?;
+        let dur = u32::try_from(dur.num_seconds()).map_err(|_| anyhow!("duration {} out of range", dur))?;
+        let key = ClutchKey::new(groupidx, keys, time, dur, start.offset().fix().local_minus_utc());
+        key.check_aligned()?;
+        Ok(key)
+    }
+
+    /// Whether the period starts on a multiple of its duration in the element's local time,
+    /// so a 15 minute period in +05:30 starts on a local quarter hour.
+    pub fn is_aligned(&self) -> bool {
+        self.dur == 0 || (self.time as i64 + self.offset as i64).rem_euclid(self.dur as i64) == 0
+    }
+
+    pub fn check_aligned(&self) -> Result<()> {
+        if self.is_aligned() {
+            Ok(())
+        } else {
+            Err(anyhow!("period start {} is not aligned to its duration of {}s", self.start_iso(), self.dur))
+        }
+    }
+
+    /// the UTC offset, None if `offset` is not a valid one (a day or more)
+    pub fn utc_offset(&self) -> Option<FixedOffset> {
+        FixedOffset::east_opt(self.offset)
+    }
+
+    pub fn start_utc(&self) -> Option<DateTime<Utc>> {
+        Utc.timestamp_opt(i64::try_from(self.time).ok()?, 0).single()
+    }
+
+    /// start of the period in the element's local time
+    pub fn start(&self) -> Option<DateTime<FixedOffset>> {
+        Some(self.start_utc()?.with_timezone(&self.utc_offset()?))
+    }
+
+    pub fn end(&self) -> Option<DateTime<FixedOffset>> {
+        Some(self.start()? + self.duration())
+    }
+
+    pub fn duration(&self) -> chrono::Duration {
+        chrono::Duration::seconds(self.dur as i64)
+    }
+
+    /// The start as ISO-8601 with the offset, like 2020-06-01T10:15:00+02:00, or the bare
+    /// seconds if it cannot be shown as a date.
+    pub fn start_iso(&self) -> String {
+        self.start().map_or_else(|| self.time.to_string(), |t| t.to_rfc3339_opts(SecondsFormat::Secs, true))
+    }
+
     fn new_empty(self: &Self) -> Self {
         ClutchKey {
             groupidx: 0,
@@ -194,7 +514,7 @@ impl ClutchKey {
 
 impl OmGroup {
     #[inline(always)]
-    fn find_setup_meta_slot(&mut self, id: u32, kind: &OmType) -> usize {
+    pub(crate) fn find_setup_meta_slot(&mut self, id: u32, kind: &OmType) -> usize {
         match kind {
             TypeU32 => {
                 match self.om_map.get(&id) {
@@ -218,7 +538,18 @@ impl OmGroup {
                     }
                 }
             }
-            _ => panic!("TYPE not handled yet"),
+            OmType::TypeString => {
+                match self.om_map.get(&id) {
+                    Some(meta) => meta.slot,
+                    None => {
+                        let this_slot = self.omstr_slots;
+                        self.omstr_slots += 1;
+                        self.om_map.insert(id, OmMeta { kind: OmType::TypeString, id, slot: this_slot });
+                        this_slot
+                    }
+                }
+            }
+            _ => panic!("OM id: {} of type {} not handled yet, only u32, f64 and str are", id, kind),
         }
     }
 }
@@ -247,7 +578,7 @@ impl Ord for ClutchKey {
 impl ToString for ClutchKey {
     fn to_string(&self) -> String {
         let s = self.keys.replace('\0', ", ");
-        format!("g:{} t:{} d:{} o:{} k:{}", self.groupidx, self.time, self.dur, self.offset, s)
+        format!("g:{} t:{} d:{} k:{}", self.groupidx, self.start_iso(), self.dur, s)
     }
 }
 
@@ -280,6 +611,12 @@ impl ClutchMeta {
             om32_slots: 0,
             om64_slots: 0,
             omstr_slots: 0,
+            sparse_ratio: DEFAULT_SPARSE_RATIO,
+            retention: None,
+            track_om_ingest: false,
+            history_depth: 0,
+            inventory: None,
+            fill_missing: false,
         };
         self.groups.push(g);
         self.group_map.insert(String::from(group), next_id);
@@ -309,193 +646,1458 @@ impl ClutchMeta {
         self.groups.get_mut(idx as usize)
     }
 
+    pub fn get_group(&self, idx: u16) -> Option<&OmGroup> {
+        self.groups.get(idx as usize)
+    }
+
+    pub fn groups(&self) -> impl Iterator<Item = &OmGroup> {
+        self.groups.iter()
+    }
+
     pub fn optimize(&mut self) {
         for g in self.groups.iter_mut() {
             g.om_map.shrink_to_fit();
         }
     }
 
+    /// Renumbers the slots of every group densely in their current order, dropping the
+    /// holes left by retired OMs, and rewrites every clutch in `cs` to match.  `cs` must
+    /// hold every clutch built against this meta or the ones left out will read garbage.
+    /// Returns how many slots were reclaimed.
+    pub fn compact(&mut self, cs: &mut ClutchStore) -> usize {
+        let mut maps = Vec::with_capacity(self.groups.len());
+        let mut reclaimed = 0;
+        for g in self.groups.iter_mut() {
+            let before = g.om32_slots + g.om64_slots + g.omstr_slots;
+            maps.push(g.renumber_slots());
+            reclaimed += before - (g.om32_slots + g.om64_slots + g.omstr_slots);
+        }
+        if reclaimed == 0 {
+            return 0;
+        }
+        for (ck, cd) in cs.clutches.iter_mut() {
+            if let (Some(g), Some(map)) = (self.groups.get(ck.groupidx as usize), maps.get(ck.groupidx as usize)) {
+                cd.remap(g, map);
+            }
+        }
+        cs.recount_bytes();
+        reclaimed
+    }
+
+}
+impl MemUsage {
+    pub fn total(&self) -> usize {
+        self.key_bytes + self.value_bytes + self.null_bitmap_bytes + self.meta_bytes + self.wasted_bytes
+    }
+
+    pub(crate) fn add(&mut self, o: &MemUsage) {
+        self.clutches += o.clutches;
+        self.key_bytes += o.key_bytes;
+        self.value_bytes += o.value_bytes;
+        self.null_bitmap_bytes += o.null_bitmap_bytes;
+        self.meta_bytes += o.meta_bytes;
+        self.wasted_bytes += o.wasted_bytes;
+    }
+}
+
+impl std::fmt::Display for MemUsage {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let m = |v: usize| if v == 0 { "0".to_string() } else { crate::util::mem_metric_digit(v, 4) };
+        write!(f, "clutches: {} total: {} keys: {} values: {} nulls: {} meta: {} wasted: {}",
+               self.clutches, m(self.total()), m(self.key_bytes), m(self.value_bytes),
+               m(self.null_bitmap_bytes), m(self.meta_bytes), m(self.wasted_bytes))
+    }
+}
+
+/// old slot -> new slot of each slot width, None for slots no OM uses any more
+struct SlotMap {
+    om32: Vec<Option<usize>>,
+    om64: Vec<Option<usize>>,
+    omstr: Vec<Option<usize>>,
+}
+
+fn remap_slot(map: &[Option<usize>], slot: usize) -> Option<usize> {
+    map.get(slot).copied().flatten()
+}
+
+impl OmGroup {
+    /// Loads the keys expected every period from a file, see `completeness::read_inventory`.
+    /// Returns how many there are.
+    pub fn load_inventory(&mut self, path: &std::path::Path) -> Result<usize> {
+        let keys = crate::completeness::read_inventory(path)?;
+        let n = keys.len();
+        self.inventory = Some(keys);
+        Ok(n)
+    }
+
+    /// Forgets OM `id` so it is no longer read, merged or derived.  Its slot stays in use
+    /// (values already in clutches are just unreachable) until `ClutchMeta::compact`.
+    /// Re-adding the id later gives it a new slot.
+    pub fn retire_om(&mut self, id: u32) -> Option<OmMeta> {
+        self.om_map.remove(&id)
+    }
+
+    /// gives the known OMs consecutive slots, keeping their order within each width
+    fn renumber_slots(&mut self) -> SlotMap {
+        let mut map = SlotMap {
+            om32: vec![None; self.om32_slots],
+            om64: vec![None; self.om64_slots],
+            omstr: vec![None; self.omstr_slots],
+        };
+        let mut metas: Vec<&mut OmMeta> = self.om_map.values_mut().collect();
+        metas.sort_unstable_by_key(|m| m.slot);
+        let (mut n32, mut n64, mut nstr) = (0, 0, 0);
+        for meta in metas {
+            let (slots, next) = match meta.kind {
+                TypeU32 => (&mut map.om32, &mut n32),
+                TypeF64 => (&mut map.om64, &mut n64),
+                OmType::TypeString => (&mut map.omstr, &mut nstr),
+                _ => continue,
+            };
+            slots[meta.slot] = Some(*next);
+            meta.slot = *next;
+            *next += 1;
+        }
+        self.om32_slots = n32;
+        self.om64_slots = n64;
+        self.omstr_slots = nstr;
+        map
+    }
+
+    /// bytes held by the id -> slot map and the group name
+    pub fn meta_bytes(&self) -> usize {
+        // hashbrown style table: a control byte per bucket on top of the entry itself
+        let entry = std::mem::size_of::<u32>() + std::mem::size_of::<OmMeta>() + 1;
+        std::mem::size_of::<OmGroup>() + self.om_map.capacity() * entry + self.group.capacity()
+    }
 }
+
 impl ClutchStore {
+    /// Merges `other` into this store clutch by clutch, see `ClutchData::merge_from`.
+    /// Returns the number of values copied.
+    pub fn merge(&mut self, cm: &ClutchMeta, other: &ClutchStore, overwrite: bool) -> usize {
+        let mut cnt = 0;
+        for (ck, od) in &other.clutches {
+            let group = cm.groups.get(ck.groupidx as usize).expect("clutch group not in meta");
+            cnt += self.find_or_add_clutchdata(group, ck).merge_from(group, od, overwrite);
+        }
+        cnt
+    }
+
+    /// Walks every clutch to add up what it holds.  This is O(clutches) so it is meant
+    /// for reporting, not for calling per insert.
+    pub fn memory_usage(&self, cm: &ClutchMeta) -> StoreMemUsage {
+        let mut mu = StoreMemUsage::default();
+        mu.groups.resize(cm.groups.len(), MemUsage::default());
+        for (g, gm) in cm.groups.iter().zip(mu.groups.iter_mut()) {
+            gm.meta_bytes = g.meta_bytes();
+        }
+        for (ck, cd) in &self.clutches {
+            let gi = ck.groupidx as usize;
+            if mu.groups.len() <= gi {
+                mu.groups.resize(gi + 1, MemUsage::default());
+            }
+            let gm = &mut mu.groups[gi];
+            gm.add(&cd.memory_usage());
+            gm.key_bytes += std::mem::size_of::<ClutchKey>() + ck.keys.capacity();
+        }
+        for gm in &mu.groups {
+            mu.total.add(gm);
+        }
+        mu
+    }
+
     pub fn new() -> ClutchStore {
         ClutchStore {
             clutches: BTreeMap::new(),
+            periods: BTreeMap::new(),
+            budget: Budget::Unlimited,
+            bytes: 0,
+            writing: None,
+            overflows: 0,
+            on_evict: None,
+            life: Lifecycle {
+                grace: std::time::Duration::from_secs(0),
+                late: LatePolicy::Accept,
+                closed: BTreeSet::new(),
+                watermark: None,
+                durs: BTreeMap::new(),
+                hooks: vec![],
+                late_store: None,
+                late_counts: vec![],
+                key_checks: vec![],
+                period_keys: BTreeMap::new(),
+            },
+            ingest_time: 0,
         }
     }
 
     pub fn clear_oms(&mut self) {
         self.clutches.clear();
+        self.periods.clear();
+        self.clear_lifecycle();
+        self.recount_bytes();
     }
 
-    pub fn find_or_add_clutchdata(self: &mut Self, group: &OmGroup, key: &ClutchKey) -> &mut ClutchData {
-        let mut add_key = 0;
+    /// forgets which periods are closed, keeping grace, policy and hooks
+    fn clear_lifecycle(&mut self) {
+        self.life.closed.clear();
+        self.life.watermark = None;
+        self.life.durs.clear();
+        self.life.late_store = None;
+        self.life.late_counts.clear();
+        self.life.key_checks.clear();
+        self.life.period_keys.clear();
+    }
 
-        let val = self.clutches
-            .entry(key.clone())
-            .or_insert(ClutchData::new(group.om32_slots, group.om64_slots));
+    /// Sets the wall clock time (epoch secs) stamped on clutches written from here on,
+    /// normally the time the data being loaded was received.  0 stops the stamping.
+    pub fn set_ingest_time(&mut self, t: u64) {
+        self.ingest_time = t;
+    }
 
-        // let val = if self.clutches.contains_key(&key) {
-        //     inc_keys();
-        //     //println!("KEY returning existing {}", key.to_string());
-        //     self.clutches.get_mut(&key).unwrap()
-        // } else {
-        //     add_key += 1;
-        //     let newkey = key.create_copy();
-        //     //println!("KEY new {}", newkey.to_string());
-        //     self.clutches.insert(key.create_copy(),
-        //                          ClutchData::new(o32,
-        //                                          o64 as usize));
-        //     self.clutches.get_mut(&key).unwrap()
-        // };
+    /// Clutches as they were at ingest time `t`: ones first written after it are left
+    /// out.  Read values through `ClutchData::get_value_as_of`.
+    pub fn iter_as_of(&self, t: u64) -> impl Iterator<Item = (&ClutchKey, &ClutchData)> {
+        self.clutches.iter().filter(move |(_, cd)| cd.x().ingested <= t)
+    }
 
-        val
+    /// How many clutch writes for group `groupidx` arrived for an already closed period.
+    pub fn late_arrivals(&self, groupidx: u16) -> u64 {
+        self.life.late_counts.get(groupidx as usize).copied().unwrap_or(0)
     }
 
-    pub fn clear_data(self: &mut Self) {
-        self.clutches.clear();
+    /// How long after its end (time + dur) a period stays open for late data.
+    pub fn set_grace(&mut self, grace: std::time::Duration) {
+        self.life.grace = grace;
     }
-    pub fn clear_all(self: &mut Self) {
-        self.clutches.clear();
+
+    pub fn set_late_policy(&mut self, late: LatePolicy) {
+        self.life.late = late;
     }
-}
 
-impl ClutchData {
-    fn new(om32_size: usize, om64_size: usize) -> Self {
-        let om32 = max(om32_size, RESIZE_INC);
-        let om64 = max(om64_size, RESIZE_INC);
-        ClutchData {
-            om_null32: BitVec::from_elem(om32_size, false),
-            om_null64: BitVec::from_elem(om64_size, false),
-            om32: vec![0u32; om32],
-            om64: vec![0u64; om64],
-            om_str: vec![],
-        }
+    /// Adds a hook run on every period close - to export, roll up or alarm on it.
+    pub fn on_close<F>(&mut self, f: F)
+        where F: 'static + Send + FnMut(&ClutchMeta, &ClutchStore, u64)
+    {
+        self.life.hooks.push(Box::new(f));
     }
 
-    // TODO:  must check that id is NOT mapped 2 two different lost 32 vs 64
+    pub fn is_closed(&self, time: u64) -> bool {
+        self.life.closed.contains(&time)
+    }
 
-    pub fn get_value(&self, meta: &OmMeta) -> OmValue {
-        match meta.kind {
-            TypeU32 => {
-                if self.om_null32[meta.slot] {
-                    OmValue::U32(*self.om32.get(meta.slot).unwrap())
-                } else {
-                    OmValue::NULL
-                }
+    /// true if a write for `key` would land in a closed period
+    pub fn is_late(&self, key: &ClutchKey) -> bool {
+        self.is_closed(key.time) || self.life.watermark.is_some_and(|now| {
+            key.time + key.dur as u64 + self.life.grace.as_secs() <= now
+        })
+    }
+
+    /// Closes period `time`, checks it against the inventory of every group that has one
+    /// and runs the close hooks on it.  Returns false if it was already closed.
+    pub fn close_period(&mut self, cm: &ClutchMeta, time: u64) -> bool {
+        if !self.life.closed.insert(time) {
+            return false;
+        }
+        let dur = self.life.durs.remove(&time).unwrap_or(0);
+        for g in cm.groups().filter(|g| g.inventory.is_some()) {
+            self.check_keys(g, time, dur);
+        }
+        let mut hooks = std::mem::take(&mut self.life.hooks);
+        for h in hooks.iter_mut() {
+            h(cm, self, time);
+        }
+        self.life.hooks = hooks;
+        true
+    }
+
+    /// Compares the keys of `group` in period `time` with its inventory, adding
+    /// placeholders for the missing if the group asks for them.  They get the duration and
+    /// offset of the group's other clutches in the period, or `dur` and 0 if it has none.
+    fn check_keys(&mut self, group: &OmGroup, time: u64, dur: u32) {
+        let inv = group.inventory.as_ref().unwrap();
+        let keys = self.life.period_keys.remove(&(group.idx, time)).unwrap_or_default();
+        let present: BTreeSet<&str> = keys.iter().map(|ck| ck.keys.as_str()).collect();
+        let (pdur, offset) = keys.iter().next_back().map_or((dur, 0), |ck| (ck.dur, ck.offset));
+        let missing: Vec<String> = inv.iter().filter(|k| !present.contains(k.as_str())).cloned().collect();
+        let unexpected = present.iter().filter(|k| !inv.contains(**k)).map(|k| k.to_string()).collect();
+        if group.fill_missing {
+            // placeholders are not new data, they never push anything out of the budget
+            for k in missing.iter() {
+                let key = ClutchKey::new(group.idx, k.clone(), time, pdur, offset);
+                self.entry(group, &key).set_suspect(SuspectReason::Missing);
             }
-            TypeF64 => {
-                if self.om_null64[meta.slot] {
-                    let tmp = *self.om64.get(meta.slot).unwrap();
-                    OmValue::F64(unsafe { std::mem::transmute::<u64, f64>(tmp) })
-                } else {
-                    OmValue::NULL
+        }
+        self.life.key_checks.push(KeyCheck { group: group.idx, time, missing, unexpected });
+    }
+
+    /// inventory checks of the periods closed so far, in the order they closed
+    pub fn key_checks(&self) -> &[KeyCheck] {
+        &self.life.key_checks
+    }
+
+    pub fn take_key_checks(&mut self) -> Vec<KeyCheck> {
+        std::mem::take(&mut self.life.key_checks)
+    }
+
+    /// Closes, oldest first, every open period whose end plus the grace window is at or
+    /// before `now` (epoch seconds).  Returns the times closed.
+    pub fn close_due(&mut self, cm: &ClutchMeta, now: u64) -> Vec<u64> {
+        let grace = self.life.grace.as_secs();
+        self.life.watermark = Some(self.life.watermark.map_or(now, |w| w.max(now)));
+        let due: Vec<u64> = self.life.durs.iter()
+            .filter(|(t, d)| *t + **d as u64 + grace <= now)
+            .map(|(t, _)| *t)
+            .collect();
+        due.into_iter().filter(|t| self.close_period(cm, *t)).collect()
+    }
+
+    /// Like `find_or_add_clutchdata` but for periods already closed the late policy
+    /// applies: an error for `Reject`, or the clutch in the late store for `SideChannel`.
+    pub fn write_clutchdata(&mut self, group: &OmGroup, key: &ClutchKey) -> Result<&mut ClutchData> {
+        if !self.is_late(key) {
+            return Ok(self.find_or_add_clutchdata(group, key));
+        }
+        let gi = group.idx as usize;
+        if self.life.late_counts.len() <= gi {
+            self.life.late_counts.resize(gi + 1, 0);
+        }
+        self.life.late_counts[gi] += 1;
+        match self.life.late {
+            LatePolicy::Accept => Ok(self.find_or_add_clutchdata(group, key)),
+            LatePolicy::Reject => Err(anyhow!("period {} of {} is closed", key.time, key.keys.replace('\0', ", "))),
+            LatePolicy::SideChannel => {
+                // the placeholder stays, marked as reported late
+                if let Some(cd) = self.clutches.get_mut(key).filter(|cd| cd.suspect == Some(SuspectReason::Missing)) {
+                    cd.suspect = Some(SuspectReason::Late);
                 }
+                let ingest_time = self.ingest_time;
+                let late = self.life.late_store.get_or_insert_with(|| Box::new(ClutchStore::new()));
+                late.set_ingest_time(ingest_time);
+                Ok(late.find_or_add_clutchdata(group, key))
             }
-            _ => panic!("error in get value, kind not mapped"),
         }
     }
-    #[inline(always)]
-    pub fn is_32_set(&mut self, slot: usize) -> bool {
-        if self.om_null32.len() < slot+1 {
-            false
-        } else {
-            match self.om_null32.get(slot) {
-                None => false,
-                Some(b)=> b
+
+    /// Takes the clutches routed aside as late so far.
+    pub fn take_late(&mut self) -> ClutchStore {
+        self.life.late_store.take().map_or_else(ClutchStore::new, |b| *b)
+    }
+
+    /// Either limit is kept on every new key.  Setting a `Budget::Bytes` walks the store
+    /// once, from then on the bytes are counted as clutches are added, written and dropped.
+    pub fn set_budget(&mut self, budget: Budget) {
+        self.budget = budget;
+        self.recount_bytes();
+    }
+
+    fn recount_bytes(&mut self) {
+        self.writing = None;
+        self.bytes = match self.budget {
+            Budget::Bytes(_) => self.data_bytes(),
+            _ => 0,
+        };
+    }
+
+    /// counts what the clutch last handed out for writing grew or shrank by since
+    fn settle_bytes(&mut self) {
+        if let Some((k, was)) = self.writing.take() {
+            if let Some(cd) = self.clutches.get(&k) {
+                self.bytes = (self.bytes + clutch_bytes(&k, cd)).saturating_sub(was);
             }
         }
     }
-    #[inline(always)]
-    pub fn is_64_set(&mut self, slot: usize) -> bool {
-        if self.om_null64.len() < slot+1 {
-            false
-        } else {
-            match self.om_null64.get(slot) {
-                None => false,
-                Some(b)=> b
-            }
+
+    /// takes a dropped clutch out of the byte count
+    fn uncount(&mut self, ck: &ClutchKey, cd: &ClutchData) {
+        if let Budget::Bytes(_) = self.budget {
+            self.bytes = self.bytes.saturating_sub(clutch_bytes(ck, cd));
         }
     }
-    #[inline(always)]
-    pub fn set_64(&mut self, slot: usize) {
-        if self.om_null64.len() < slot+1 {
-            // println!("64 GROW TO {}", slot+1);
-            let growth = ((slot+1 / 32)+1)*32;
-            self.om_null64.grow(growth, false);
+
+    /// How many times the store went or stayed over its budget because nothing could be
+    /// evicted: a new key for the oldest period under `Budget::Clutches`, or the newest
+    /// period alone over a `Budget::Bytes` limit.
+    pub fn budget_overflows(&self) -> usize {
+        self.overflows
+    }
+
+    pub fn budget(&self) -> Budget {
+        self.budget
+    }
+
+    /// Hands evicted periods to `f` (to export or snapshot them) instead of just dropping them.
+    pub fn on_evict<F>(&mut self, f: F)
+        where F: 'static + Send + FnMut(u64, Vec<(ClutchKey, ClutchData)>)
+    {
+        self.on_evict = Some(Box::new(f));
+    }
+
+    /// (time, clutch count) of every period in the store, oldest first
+    pub fn periods(&self) -> impl Iterator<Item = (u64, usize)> + '_ {
+        self.periods.iter().map(|(t, c)| (*t, *c))
+    }
+
+    /// Removes and returns every clutch of period `time`.
+    pub fn take_period(&mut self, time: u64) -> Vec<(ClutchKey, ClutchData)> {
+        if self.periods.remove(&time).is_none() {
+            return vec![];
         }
-        // println!("64 set slot {} with len: {}", slot, self.om_null64.len());
-        self.om_null64.set(slot, true);
+        self.settle_bytes();
+        // an open period with nothing left in it has nothing to close
+        self.life.durs.remove(&time);
+        self.life.period_keys.retain(|(_, t), _| *t != time);
+        let keys: Vec<ClutchKey> = self.clutches.keys().filter(|k| k.time == time).cloned().collect();
+        keys.into_iter()
+            .map(|k| {
+                let cd = self.clutches.remove(&k).unwrap();
+                self.uncount(&k, &cd);
+                (k, cd)
+            })
+            .collect()
     }
 
-    #[inline(always)]
-    pub fn set_32(&mut self, slot: usize) {
-        if self.om_null32.len() < slot+1 {
-            let growth = ((slot+1 / 32)+1)*32;
-            self.om_null32.grow(growth, false);
+    /// Evicts the oldest period if it is older than `before`, passing it to the evict
+    /// callback.  Returns the period's time and how many clutches it had.
+    fn evict_oldest(&mut self, before: u64) -> Option<(u64, usize)> {
+        let time = *self.periods.keys().next()?;
+        if time >= before {
+            return None;
+        }
+        let gone = self.take_period(time);
+        let cnt = gone.len();
+        if let Some(f) = self.on_evict.as_mut() {
+            f(time, gone);
         }
-        // println!("32 set slot {} with len: {}", slot, self.om_null32.len());
-        self.om_null32.set(slot, true);
+        Some((time, cnt))
     }
 
-    #[inline(always)]
-    pub fn add_om_u32(self: &mut Self, overwrite: bool, group: &mut OmGroup, id: u32, val: u32) -> Result<()> {
-        let slot = group.find_setup_meta_slot(id, &TypeU32);
+    /// Drops one clutch, returning its data.
+    pub fn remove(&mut self, key: &ClutchKey) -> Option<ClutchData> {
+        self.remove_entry(key).map(|(_, cd)| cd)
+    }
 
-        if !overwrite && self.is_32_set(slot) {
-            Err(anyhow!("duplicate u32 OM id: {} val: {}", id,val))
-        } else {
-            self.set_32(slot);
-            if self.om32.len() < slot + 1 {
-                //inc_resizes();
-                self.om32.resize(slot + RESIZE_INC, 0);
+    fn remove_entry(&mut self, key: &ClutchKey) -> Option<(ClutchKey, ClutchData)> {
+        self.settle_bytes();
+        let (k, cd) = self.clutches.remove_entry(key)?;
+        self.uncount(&k, &cd);
+        if let Some(keys) = self.life.period_keys.get_mut(&(k.groupidx, k.time)) {
+            keys.remove(&k);
+        }
+        if let Some(cnt) = self.periods.get_mut(&k.time) {
+            *cnt -= 1;
+            if *cnt == 0 {
+                self.periods.remove(&k.time);
+                self.life.durs.remove(&k.time);
             }
-            //inc_oms();
-            self.om32[slot] = val;
-            Ok(())
         }
+        Some((k, cd))
     }
-    #[inline(always)]
-    pub fn add_om_f64(self: &mut Self, overwrite: bool, group: &mut OmGroup, id: u32, val: f64) -> Result<()> {
-        let slot = group.find_setup_meta_slot(id, &TypeF64);
-
-        if !overwrite && self.is_64_set(slot) {
-            Err(anyhow!("duplicate f64 OM id: {} val: {}", id,val))
-        } else {
-            self.set_64(slot);
-            if self.om64.len() < slot + 1 {
-                // inc_resizes();
-                self.om64.resize(slot + RESIZE_INC, 0);
-            }
-            // inc_oms();
 
-            unsafe { self.om64[slot] =  std::mem::transmute::<f64, u64>(val) };
-            Ok(())
+    /// Drops every clutch whose group retention has run out by `now` (epoch seconds),
+    /// that is `time + dur + retention <= now`, and returns what was removed.
+    pub fn expire(&mut self, cm: &ClutchMeta, now: u64) -> Vec<(ClutchKey, ClutchData)> {
+        let expired = |k: &ClutchKey| match cm.groups.get(k.groupidx as usize).and_then(|g| g.retention) {
+            Some(keep) => k.time + k.dur as u64 + keep.as_secs() <= now,
+            None => false,
+        };
+        if cm.groups.iter().all(|g| g.retention.is_none()) {
+            return vec![];
         }
+        let keys: Vec<ClutchKey> = self.clutches.keys().filter(|k| expired(k)).cloned().collect();
+        keys.iter().filter_map(|k| self.remove_entry(k)).collect()
     }
-}
 
-pub fn dump(cm: &ClutchMeta, cs: &ClutchStore, first_last: bool) {
-    let mut at = 0;
-    if cs.clutches.len() == 0 { eprintln!("HEY no clutches here?"); }
-    for (ck, cd) in &cs.clutches {
-        at += 1;
-        if !first_last || at == 1 || at == cs.clutches.len() {
-            let g = cm.groups.get(ck.groupidx as usize).unwrap();
+    fn data_bytes(&self) -> usize {
+        self.clutches.iter().map(|(ck, cd)| clutch_bytes(ck, cd)).sum()
+    }
 
-            println!("{} {{ group: {} key: {}  time: {} dur: {} os: {}", at, &g.group, ck.keys.replace('\0', ", "), ck.time, ck.dur, ck.offset);
-            let mut non_null = 0;
-            let mut null = 0;
-            for (id, meta) in &g.om_map {
-                match cd.get_value(&meta) {
-                    OmValue::NULL => null += 1,
-                    _ => non_null += 1,
+    /// Evicts oldest periods until the store is within its budget.  The newest period is
+    /// never evicted so a store always keeps what is currently being filled.  Returns
+    /// the number of clutches evicted.
+    pub fn enforce_budget(&mut self) -> usize {
+        let newest = match self.periods.keys().next_back() {
+            Some(t) => *t,
+            None => return 0,
+        };
+        let mut evicted = 0;
+        match self.budget {
+            Budget::Unlimited => {}
+            Budget::Clutches(max) => {
+                while self.clutches.len() > max {
+                    match self.evict_oldest(newest) {
+                        Some((_, cnt)) => evicted += cnt,
+                        None => {
+                            self.overflows += 1;
+                            break;
+                        }
+                    }
                 }
             }
-
-            print!("\tc: {}/{}  ", non_null, null);
-            print!("{}",
-                   &g.om_map.iter().map(|x|
-                       format!("{}:{} {}", x.0, cd.get_value(x.1), &x.1.kind)).
+            Budget::Bytes(max) => {
+                self.settle_bytes();
+                while self.bytes > max {
+                    match self.evict_oldest(newest) {
+                        Some((_, cnt)) => evicted += cnt,
+                        None => {
+                            self.overflows += 1;
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        evicted
+    }
+
+    pub fn find_or_add_clutchdata(self: &mut Self, group: &OmGroup, key: &ClutchKey) -> &mut ClutchData {
+        let mut add_key = 0;
+
+        match self.budget {
+            Budget::Clutches(max) => {
+                // make room for a new key by dropping periods older than the one it is in
+                while self.clutches.len() >= max && !self.clutches.contains_key(key) {
+                    if self.evict_oldest(key.time).is_none() {
+                        self.overflows += 1;
+                        break;
+                    }
+                }
+            }
+            Budget::Bytes(max) => {
+                self.settle_bytes();
+                while self.bytes > max && !self.clutches.contains_key(key) {
+                    if self.evict_oldest(key.time).is_none() {
+                        self.overflows += 1;
+                        break;
+                    }
+                }
+            }
+            Budget::Unlimited => {}
+        }
+
+        let val = self.entry(group, key);
+
+        // let val = if self.clutches.contains_key(&key) {
+        //     inc_keys();
+        //     //println!("KEY returning existing {}", key.to_string());
+        //     self.clutches.get_mut(&key).unwrap()
+        // } else {
+        //     add_key += 1;
+        //     let newkey = key.create_copy();
+        //     //println!("KEY new {}", newkey.to_string());
+        //     self.clutches.insert(key.create_copy(),
+        //                          ClutchData::new(o32,
+        //                                          o64 as usize));
+        //     self.clutches.get_mut(&key).unwrap()
+        // };
+
+        val
+    }
+
+    /// The clutch of `key`, added if new without regard to the budget.
+    fn entry(&mut self, group: &OmGroup, key: &ClutchKey) -> &mut ClutchData {
+        let counting = matches!(self.budget, Budget::Bytes(_));
+        if counting {
+            self.settle_bytes();
+        }
+        let periods = &mut self.periods;
+        let life = &mut self.life;
+        let mut added = false;
+        let val = self.clutches
+            .entry(key.clone())
+            .or_insert_with(|| {
+                added = true;
+                *periods.entry(key.time).or_insert(0) += 1;
+                if !life.closed.contains(&key.time) {
+                    let d = life.durs.entry(key.time).or_insert(0);
+                    *d = max(*d, key.dur);
+                    if group.inventory.is_some() {
+                        life.period_keys.entry((group.idx, key.time)).or_default().insert(key.clone());
+                    }
+                }
+                ClutchData::new(group)
+            });
+        val.writing_at = self.ingest_time;
+        if counting {
+            // what gets written to it is settled on the next call
+            let now = clutch_bytes(key, val);
+            if added {
+                self.bytes += now;
+            }
+            self.writing = Some((key.clone(), now));
+        }
+        val
+    }
+
+    pub fn get(&self, key: &ClutchKey) -> Option<&ClutchData> {
+        self.clutches.get(key)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&ClutchKey, &ClutchData)> {
+        self.clutches.iter()
+    }
+
+    /// Clutches of one group and full key with `time` in `from..to`, in time order.  Keys
+    /// sort first, so this is a range of the map rather than a scan of it.
+    pub fn key_range(&self, groupidx: u16, keys: &str, from: u64, to: u64) -> std::collections::btree_map::Range<'_, ClutchKey, ClutchData> {
+        let lo = ClutchKey::new(groupidx, keys.to_string(), from, 0, i32::MIN);
+        let hi = ClutchKey::new(groupidx, keys.to_string(), to.max(from), 0, i32::MIN);
+        self.clutches.range(lo..hi)
+    }
+
+    pub fn len(&self) -> usize {
+        self.clutches.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.clutches.is_empty()
+    }
+
+    pub fn clear_data(self: &mut Self) {
+        self.clutches.clear();
+        self.periods.clear();
+        self.clear_lifecycle();
+        self.recount_bytes();
+    }
+    pub fn clear_all(self: &mut Self) {
+        self.clutches.clear();
+        self.periods.clear();
+        self.clear_lifecycle();
+        self.recount_bytes();
+    }
+}
+
+/// what set_null needs of either slot width
+trait NullableSlots {
+    fn has(&self, slot: usize) -> bool;
+    fn unset(&mut self, slot: usize) -> bool;
+}
+
+impl<T: Copy + Default> NullableSlots for OmSlots<T> {
+    fn has(&self, slot: usize) -> bool {
+        self.is_set(slot)
+    }
+    fn unset(&mut self, slot: usize) -> bool {
+        self.clear(slot)
+    }
+}
+
+/// meta of OM `id` in `group`, checked to be of `kind`
+fn typed_meta(group: &OmGroup, id: u32, kind: OmType) -> Result<&OmMeta> {
+    let meta = group.om_map.get(&id)
+        .ok_or_else(|| anyhow!("OM id: {} not known in group {}", id, &group.group))?;
+    if std::mem::discriminant(&meta.kind) != std::mem::discriminant(&kind) {
+        return Err(anyhow!("OM id: {} is {} not {}", id, &meta.kind, &kind));
+    }
+    Ok(meta)
+}
+
+/// what one clutch counts for against a `Budget::Bytes`
+fn clutch_bytes(ck: &ClutchKey, cd: &ClutchData) -> usize {
+    std::mem::size_of::<ClutchKey>() + ck.keys.capacity() + cd.memory_usage().total()
+}
+
+fn count_set(set: &BitVec) -> usize {
+    set.blocks().map(|b| b.count_ones() as usize).sum()
+}
+
+#[inline(always)]
+fn sorted_contains(v: &[u32], slot: usize) -> bool {
+    !v.is_empty() && v.binary_search(&(slot as u32)).is_ok()
+}
+
+#[inline(always)]
+fn sorted_insert(v: &mut Vec<u32>, slot: usize) {
+    if let Err(i) = v.binary_search(&(slot as u32)) {
+        v.insert(i, slot as u32);
+    }
+}
+
+#[inline(always)]
+fn sorted_remove(v: &mut Vec<u32>, slot: usize) -> bool {
+    if v.is_empty() {
+        return false;
+    }
+    match v.binary_search(&(slot as u32)) {
+        Ok(i) => {
+            v.remove(i);
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+impl<T: Copy + Default> OmSlots<T> {
+    /// new clutches in big sparse-enabled groups start out sparse
+    fn new(size: usize, ratio: f32) -> Self {
+        if ratio > 0.0 && size >= SPARSE_MIN_SLOTS {
+            OmSlots::Sparse(vec![])
+        } else {
+            OmSlots::dense(size)
+        }
+    }
+
+    fn dense(size: usize) -> Self {
+        OmSlots::Dense {
+            set: BitVec::from_elem(size, false),
+            vals: vec![T::default(); max(size, RESIZE_INC)],
+        }
+    }
+
+    #[inline(always)]
+    fn get(&self, slot: usize) -> Option<T> {
+        match self {
+            OmSlots::Dense { set, vals } => match set.get(slot) {
+                Some(true) => Some(vals[slot]),
+                _ => None,
+            },
+            OmSlots::Sparse(pairs) => match pairs.binary_search_by_key(&(slot as u32), |p| p.0) {
+                Ok(i) => Some(pairs[i].1),
+                Err(_) => None,
+            },
+        }
+    }
+
+    #[inline(always)]
+    fn is_set(&self, slot: usize) -> bool {
+        match self {
+            OmSlots::Dense { set, .. } => set.get(slot).unwrap_or(false),
+            OmSlots::Sparse(pairs) => pairs.binary_search_by_key(&(slot as u32), |p| p.0).is_ok(),
+        }
+    }
+
+    fn is_sparse(&self) -> bool {
+        matches!(self, OmSlots::Sparse(_))
+    }
+
+    /// Unsets a slot, returning true if it held a value.
+    fn clear(&mut self, slot: usize) -> bool {
+        match self {
+            OmSlots::Dense { set, .. } => {
+                let was = set.get(slot).unwrap_or(false);
+                if was {
+                    set.set(slot, false);
+                }
+                was
+            }
+            OmSlots::Sparse(pairs) => match pairs.binary_search_by_key(&(slot as u32), |p| p.0) {
+                Ok(i) => {
+                    pairs.remove(i);
+                    true
+                }
+                Err(_) => false,
+            },
+        }
+    }
+
+    /// (slot, value) of every set slot in slot order
+    fn iter(&self) -> Box<dyn Iterator<Item = (usize, T)> + '_> {
+        match self {
+            OmSlots::Dense { set, vals } => Box::new(set.iter().enumerate()
+                .filter(|(_, b)| *b)
+                .map(move |(slot, _)| (slot, vals[slot]))),
+            OmSlots::Sparse(pairs) => Box::new(pairs.iter().map(|(slot, v)| (*slot as usize, *v))),
+        }
+    }
+
+    /// number of slots holding a value
+    fn count(&self) -> usize {
+        match self {
+            OmSlots::Dense { set, .. } => count_set(set),
+            OmSlots::Sparse(pairs) => pairs.len(),
+        }
+    }
+
+    fn make_dense(&mut self, size: usize) {
+        if let OmSlots::Sparse(pairs) = self {
+            let size = max(size, pairs.last().map_or(0, |p| p.0 as usize + 1));
+            let mut d = OmSlots::dense(size);
+            if let OmSlots::Dense { set, vals } = &mut d {
+                for (slot, v) in pairs.iter() {
+                    set.set(*slot as usize, true);
+                    vals[*slot as usize] = *v;
+                }
+            }
+            *self = d;
+        }
+    }
+
+    fn make_sparse(&mut self) {
+        if let OmSlots::Dense { set, vals } = self {
+            let pairs = set.iter().enumerate()
+                .filter(|(_, b)| *b)
+                .map(|(slot, _)| (slot as u32, vals[slot]))
+                .collect();
+            *self = OmSlots::Sparse(pairs);
+        }
+    }
+
+    /// Picks the representation for a clutch in a group with `slots` slots of this width.
+    fn balance(&mut self, slots: usize, ratio: f32) {
+        if ratio <= 0.0 || slots < SPARSE_MIN_SLOTS {
+            self.make_dense(slots);
+        } else if (self.count() as f32) < ratio * slots as f32 {
+            self.make_sparse();
+        } else {
+            self.make_dense(slots);
+        }
+    }
+
+    /// Stores a value, switching representation when the fill ratio crosses the
+    /// group's threshold.  Dense clutches are only re-checked when they would have to
+    /// grow anyway, so the common in-place write stays cheap.
+    #[inline(always)]
+    fn put(&mut self, slot: usize, val: T, slots: usize, ratio: f32) {
+        match self {
+            OmSlots::Dense { set, vals } => {
+                if vals.len() < slot + 1 {
+                    if ratio > 0.0 && slots >= SPARSE_MIN_SLOTS
+                        && ((count_set(set) + 1) as f32) < ratio * slots as f32 {
+                        self.make_sparse();
+                        return self.put(slot, val, slots, ratio);
+                    }
+                    //inc_resizes();
+                    vals.resize(slot + RESIZE_INC, T::default());
+                }
+                if set.len() < slot + 1 {
+                    set.grow(vals.len() - set.len(), false);
+                }
+                set.set(slot, true);
+                vals[slot] = val;
+            }
+            OmSlots::Sparse(pairs) => {
+                match pairs.binary_search_by_key(&(slot as u32), |p| p.0) {
+                    Ok(i) => pairs[i].1 = val,
+                    Err(i) => pairs.insert(i, (slot as u32, val)),
+                }
+                if pairs.len() as f32 >= ratio * slots as f32 {
+                    self.make_dense(slots);
+                }
+            }
+        }
+    }
+}
+
+impl<T: Copy + Default> OmSlots<T> {
+    /// a copy with every slot moved per `map`, unmapped slots are dropped
+    fn remap(&self, map: &[Option<usize>], slots: usize, ratio: f32) -> Self {
+        let mut out = OmSlots::new(slots, ratio);
+        for (slot, v) in self.iter() {
+            if let Some(n) = remap_slot(map, slot) {
+                out.put(n, v, slots, ratio);
+            }
+        }
+        out
+    }
+}
+
+impl<T> OmSlots<T> {
+    /// (in use value bytes, bitmap bytes, wasted bytes)
+    fn memory_usage(&self) -> (usize, usize, usize) {
+        match self {
+            OmSlots::Dense { set, vals } => {
+                let used = count_set(set) * std::mem::size_of::<T>();
+                (used, set.capacity() / 8, vals.capacity() * std::mem::size_of::<T>() - used)
+            }
+            OmSlots::Sparse(pairs) => {
+                let sz = std::mem::size_of::<(u32, T)>();
+                (pairs.len() * sz, 0, (pairs.capacity() - pairs.len()) * sz)
+            }
+        }
+    }
+}
+
+impl ClutchData {
+    /// Memory held by this clutch's values - the struct itself is counted as value bytes.
+    pub fn memory_usage(&self) -> MemUsage {
+        let (v32, n32, w32) = self.om32.memory_usage();
+        let (v64, n64, w64) = self.om64.memory_usage();
+        let x = self.x();
+        let strs = x.om_str.capacity() * std::mem::size_of::<(u32, String)>()
+            + x.om_str.iter().map(|s| s.1.capacity()).sum::<usize>();
+        let extras = if self.x.is_some() { std::mem::size_of::<ClutchExtras>() } else { 0 };
+        MemUsage {
+            clutches: 1,
+            key_bytes: 0,
+            value_bytes: std::mem::size_of::<ClutchData>() + extras + v32 + v64 + strs
+                + x.quality.capacity() * std::mem::size_of::<(u32, u8)>()
+                + x.om_times.capacity() * std::mem::size_of::<(u32, u64)>()
+                + x.history.capacity() * std::mem::size_of::<(u32, OmVersion)>(),
+            null_bitmap_bytes: n32 + n64 + (x.null32.capacity() + x.null64.capacity()) * 4,
+            meta_bytes: 0,
+            wasted_bytes: w32 + w64,
+        }
+    }
+
+    fn new(group: &OmGroup) -> Self {
+        ClutchData {
+            om32: OmSlots::new(group.om32_slots, group.sparse_ratio),
+            om64: OmSlots::new(group.om64_slots, group.sparse_ratio),
+            suspect: None,
+            writing_at: 0,
+            x: None,
+        }
+    }
+
+    #[inline(always)]
+    fn x(&self) -> &ClutchExtras {
+        self.x.as_deref().unwrap_or(&NO_EXTRAS)
+    }
+
+    fn x_mut(&mut self) -> &mut ClutchExtras {
+        self.x.get_or_insert_with(Box::default)
+    }
+
+    /// value or NULL a slot holds now, None when never reported
+    fn held(&self, kind: &OmType, slot: usize) -> Option<OmValue> {
+        match kind {
+            TypeU32 => self.om32.get(slot).map(OmValue::U32)
+                .or_else(|| if self.is_32_null(slot) { Some(OmValue::NULL) } else { None }),
+            TypeF64 => self.om64.get(slot).map(|v| OmValue::F64(f64::from_bits(v)))
+                .or_else(|| if self.is_64_null(slot) { Some(OmValue::NULL) } else { None }),
+            _ => None,
+        }
+    }
+
+    /// Before `new` is written over OM `id`, keeps what it held as a version if the group
+    /// keeps history, dropping the oldest past the group's depth.  Returns false when `new`
+    /// is what the OM already holds, which is not a change to stamp.
+    fn keep_prior(&mut self, group: &OmGroup, id: u32, kind: &OmType, slot: usize, new: &OmValue) -> bool {
+        if group.history_depth == 0 && !group.track_om_ingest {
+            return true;
+        }
+        let prior = match self.held(kind, slot) {
+            None => return true,
+            Some(v) if same_value(&v, new) => return false,
+            Some(v) => v,
+        };
+        if group.history_depth > 0 {
+            let version = OmVersion { value: prior, written: self.om_ingested(id).unwrap_or(0), replaced: self.writing_at };
+            self.push_version(group.history_depth, id, version);
+        }
+        true
+    }
+
+    pub(crate) fn push_version(&mut self, depth: usize, id: u32, version: OmVersion) {
+        let history = &mut self.x_mut().history;
+        let end = history.partition_point(|(i, _)| *i <= id);
+        history.insert(end, (id, version));
+        let start = history.partition_point(|(i, _)| *i < id);
+        if end + 1 - start > depth {
+            history.remove(start);
+        }
+    }
+
+    /// Prior values of OM `id`, oldest first - empty unless the group keeps history.
+    pub fn history(&self, id: u32) -> impl Iterator<Item = &OmVersion> {
+        let history = &self.x().history;
+        let start = history.partition_point(|(i, _)| *i < id);
+        history[start..].iter().take_while(move |(i, _)| *i == id).map(|(_, v)| v)
+    }
+
+    /// (OM id, version) of every kept version, by id then oldest first
+    pub fn versions(&self) -> impl Iterator<Item = (u32, &OmVersion)> {
+        self.x().history.iter().map(|(id, v)| (*id, v))
+    }
+
+    /// sets the clutch's ingest times directly, for loading a store back from an export
+    pub(crate) fn restore_ingest(&mut self, ingested: u64, updated: u64) {
+        if ingested > 0 || updated > 0 || self.x.is_some() {
+            let x = self.x_mut();
+            x.ingested = ingested;
+            x.updated = updated;
+        }
+    }
+
+    /// sets the ingest time of OM `id` directly, 0 for none
+    pub(crate) fn restore_om_ingested(&mut self, id: u32, t: u64) {
+        match (self.x().om_times.binary_search_by_key(&id, |p| p.0), t) {
+            (Ok(i), 0) => { self.x_mut().om_times.remove(i); }
+            (Ok(i), t) => self.x_mut().om_times[i].1 = t,
+            (Err(_), 0) => {}
+            (Err(i), t) => self.x_mut().om_times.insert(i, (id, t)),
+        }
+    }
+
+    /// marks the clutch as written at ingest time `t` - 0 leaves it untracked
+    #[inline(always)]
+    fn touch(&mut self, t: u64) {
+        if t > 0 {
+            let x = self.x_mut();
+            if x.ingested == 0 {
+                x.ingested = t;
+            }
+            x.updated = t;
+        }
+    }
+
+    /// stamps a write of OM `id`, the OM itself only if the write `changed` it.  Written
+    /// to, a placeholder is not one any more.
+    #[inline(always)]
+    fn wrote(&mut self, group: &OmGroup, id: u32, changed: bool) {
+        if self.suspect == Some(SuspectReason::Missing) {
+            self.suspect = None;
+        }
+        self.touch(self.writing_at);
+        if changed {
+            self.stamp_om(group, id);
+        }
+    }
+
+    #[inline(always)]
+    fn stamp_om(&mut self, group: &OmGroup, id: u32) {
+        if group.track_om_ingest && self.x().updated > 0 {
+            let x = self.x_mut();
+            match x.om_times.binary_search_by_key(&id, |p| p.0) {
+                Ok(i) => x.om_times[i].1 = x.updated,
+                Err(i) => x.om_times.insert(i, (id, x.updated)),
+            }
+        }
+    }
+
+    /// ingest time of the first write, None when not tracked
+    pub fn ingested(&self) -> Option<u64> {
+        Some(self.x().ingested).filter(|t| *t > 0)
+    }
+
+    /// ingest time of the latest write, None when not tracked
+    pub fn updated(&self) -> Option<u64> {
+        Some(self.x().updated).filter(|t| *t > 0)
+    }
+
+    /// ingest time of the latest write of OM `id`, when its group tracks them
+    pub fn om_ingested(&self, id: u32) -> Option<u64> {
+        let times = &self.x().om_times;
+        times.binary_search_by_key(&id, |p| p.0).ok().map(|i| times[i].1)
+    }
+
+    /// The value as it stood at ingest time `t`.  `Unset` if the clutch was first written
+    /// after `t`, or with per-OM tracking if the OM was last written after `t` and no
+    /// version kept in the history was current then.  Untracked clutches always read as
+    /// they are now.
+    pub fn get_value_as_of(&self, meta: &OmMeta, t: u64) -> OmValue {
+        if self.x().ingested > t {
+            return OmValue::Unset;
+        }
+        // the oldest version replaced after t is the one that was current at t
+        if let Some(v) = self.history(meta.id).find(|v| v.replaced > t) {
+            return if v.written > t { OmValue::Unset } else { v.value.clone() };
+        }
+        if self.om_ingested(meta.id).is_some_and(|w| w > t) {
+            return OmValue::Unset;
+        }
+        self.get_value(meta)
+    }
+
+    pub fn suspect(&self) -> Option<SuspectReason> {
+        self.suspect
+    }
+
+    pub fn set_suspect(&mut self, reason: SuspectReason) {
+        self.suspect = Some(reason);
+    }
+
+    pub fn clear_suspect(&mut self) {
+        self.suspect = None;
+    }
+
+    /// Takes the value or NULL of OM `id` (and its quality bits) out of this clutch, so it
+    /// reads back as never reported.  Returns false if there was nothing to remove.
+    pub fn remove_om(&mut self, group: &OmGroup, id: u32) -> bool {
+        if let Some(x) = self.x.as_mut() {
+            x.quality.retain(|(i, _)| *i != id);
+            x.om_times.retain(|(i, _)| *i != id);
+            x.history.retain(|(i, _)| *i != id);
+        }
+        let meta = match group.om_map.get(&id) {
+            Some(meta) => meta,
+            None => return false,
+        };
+        let x = self.x.as_deref_mut();
+        match meta.kind {
+            TypeU32 => self.om32.clear(meta.slot) | x.is_some_and(|x| sorted_remove(&mut x.null32, meta.slot)),
+            TypeF64 => self.om64.clear(meta.slot) | x.is_some_and(|x| sorted_remove(&mut x.null64, meta.slot)),
+            OmType::TypeString => match x.and_then(|x| x.om_str.iter().position(|(slot, _)| *slot as usize == meta.slot).map(|i| (x, i))) {
+                Some((x, i)) => {
+                    x.om_str.remove(i);
+                    true
+                }
+                None => false,
+            },
+            _ => false,
+        }
+    }
+
+    /// rewrites the slots per a `ClutchMeta::compact` renumbering of `group`
+    fn remap(&mut self, group: &OmGroup, map: &SlotMap) {
+        self.om32 = self.om32.remap(&map.om32, group.om32_slots, group.sparse_ratio);
+        self.om64 = self.om64.remap(&map.om64, group.om64_slots, group.sparse_ratio);
+        let x = match self.x.as_mut() {
+            Some(x) => x,
+            None => return,
+        };
+        for (nulls, m) in [(&mut x.null32, &map.om32), (&mut x.null64, &map.om64)] {
+            *nulls = nulls.iter().filter_map(|s| remap_slot(m, *s as usize).map(|n| n as u32)).collect();
+            nulls.sort_unstable();
+        }
+        x.om_str = x.om_str.drain(..)
+            .filter_map(|(slot, s)| remap_slot(&map.omstr, slot as usize).map(|n| (n as u32, s)))
+            .collect();
+        x.quality.retain(|(id, _)| group.om_map.contains_key(id));
+        x.om_times.retain(|(id, _)| group.om_map.contains_key(id));
+        x.history.retain(|(id, _)| group.om_map.contains_key(id));
+    }
+
+    /// quality bits of an OM, 0 when it has none
+    pub fn quality(&self, id: u32) -> u8 {
+        let quality = &self.x().quality;
+        match quality.binary_search_by_key(&id, |q| q.0) {
+            Ok(i) => quality[i].1,
+            Err(_) => 0,
+        }
+    }
+
+    /// Replaces the quality bits of an OM - 0 removes them.
+    pub fn set_quality(&mut self, id: u32, flags: u8) {
+        match self.x().quality.binary_search_by_key(&id, |q| q.0) {
+            Ok(i) if flags == 0 => { self.x_mut().quality.remove(i); }
+            Ok(i) => self.x_mut().quality[i].1 = flags,
+            Err(_) if flags == 0 => {}
+            Err(i) => self.x_mut().quality.insert(i, (id, flags)),
+        }
+    }
+
+    pub fn add_quality(&mut self, id: u32, flags: u8) {
+        let q = self.quality(id);
+        self.set_quality(id, q | flags);
+    }
+
+    /// (OM id, quality bits) of every OM with flags, by id
+    pub fn qualities(&self) -> impl Iterator<Item = (u32, u8)> + '_ {
+        self.x().quality.iter().cloned()
+    }
+
+    /// Carries the clutch level suspect flag over from clutches this one was derived from.
+    pub fn inherit_suspect(&mut self, from: &ClutchData) {
+        if self.suspect.is_none() {
+            self.suspect = from.suspect.map(|r| r.propagate());
+        }
+    }
+
+    /// Copies every value and NULL of `other` (a clutch of the same group) into this one, with
+    /// its quality flags OR-ed in and its suspect flag kept if this one has none.
+    /// Values already set here are only replaced with `overwrite`.  Returns the number
+    /// of values copied.
+    pub fn merge_from(&mut self, group: &OmGroup, other: &ClutchData, overwrite: bool) -> usize {
+        // slots of retired OMs are left behind
+        let (mut live32, mut live64) = (vec![false; group.om32_slots], vec![false; group.om64_slots]);
+        let mut livestr = vec![false; group.omstr_slots];
+        for meta in group.om_map.values() {
+            match meta.kind {
+                TypeU32 => live32[meta.slot] = true,
+                TypeF64 => live64[meta.slot] = true,
+                OmType::TypeString => livestr[meta.slot] = true,
+                _ => {}
+            }
+        }
+        let live = |l: &[bool], slot: usize| l.get(slot).copied().unwrap_or(false);
+        let mut cnt = 0;
+        for (slot, v) in other.om32.iter().filter(|(s, _)| live(&live32, *s)) {
+            if overwrite || !(self.om32.is_set(slot) || self.is_32_null(slot)) {
+                self.om32.put(slot, v, group.om32_slots, group.sparse_ratio);
+                if let Some(x) = self.x.as_mut() {
+                    sorted_remove(&mut x.null32, slot);
+                }
+                cnt += 1;
+            }
+        }
+        for (slot, v) in other.om64.iter().filter(|(s, _)| live(&live64, *s)) {
+            if overwrite || !(self.om64.is_set(slot) || self.is_64_null(slot)) {
+                self.om64.put(slot, v, group.om64_slots, group.sparse_ratio);
+                if let Some(x) = self.x.as_mut() {
+                    sorted_remove(&mut x.null64, slot);
+                }
+                cnt += 1;
+            }
+        }
+        let ox = other.x();
+        for slot in ox.null32.iter().map(|s| *s as usize).filter(|s| live(&live32, *s)) {
+            if overwrite || !(self.om32.is_set(slot) || self.is_32_null(slot)) {
+                self.om32.clear(slot);
+                sorted_insert(&mut self.x_mut().null32, slot);
+                cnt += 1;
+            }
+        }
+        for slot in ox.null64.iter().map(|s| *s as usize).filter(|s| live(&live64, *s)) {
+            if overwrite || !(self.om64.is_set(slot) || self.is_64_null(slot)) {
+                self.om64.clear(slot);
+                sorted_insert(&mut self.x_mut().null64, slot);
+                cnt += 1;
+            }
+        }
+        for (slot, v) in ox.om_str.iter().filter(|(s, _)| live(&livestr, *s as usize)) {
+            let strs = &mut self.x_mut().om_str;
+            match strs.binary_search_by_key(slot, |p| p.0) {
+                Ok(i) if overwrite => strs[i].1.clone_from(v),
+                Ok(_) => continue,
+                Err(i) => strs.insert(i, (*slot, v.clone())),
+            }
+            cnt += 1;
+        }
+        for (id, q) in other.qualities().filter(|(id, _)| group.om_map.contains_key(id)) {
+            self.add_quality(id, q);
+        }
+        if self.suspect.is_none() {
+            self.suspect = other.suspect;
+        }
+        if other.x.is_some() {
+            let x = self.x_mut();
+            if ox.ingested > 0 && (x.ingested == 0 || ox.ingested < x.ingested) {
+                x.ingested = ox.ingested;
+            }
+            x.updated = max(x.updated, ox.updated);
+            for (id, t) in ox.om_times.iter().filter(|(id, _)| group.om_map.contains_key(id)) {
+                match x.om_times.binary_search_by_key(id, |p| p.0) {
+                    Ok(i) if overwrite => x.om_times[i].1 = *t,
+                    Ok(_) => {}
+                    Err(i) => x.om_times.insert(i, (*id, *t)),
+                }
+            }
+        }
+        cnt
+    }
+
+    // TODO:  must check that id is NOT mapped 2 two different lost 32 vs 64
+
+    pub fn get_value(&self, meta: &OmMeta) -> OmValue {
+        match meta.kind {
+            TypeU32 => {
+                match self.om32.get(meta.slot) {
+                    Some(v) => OmValue::U32(v),
+                    None if sorted_contains(&self.x().null32, meta.slot) => OmValue::NULL,
+                    None => OmValue::Unset,
+                }
+            }
+            TypeF64 => {
+                match self.om64.get(meta.slot) {
+                    Some(v) => OmValue::F64(f64::from_bits(v)),
+                    None if sorted_contains(&self.x().null64, meta.slot) => OmValue::NULL,
+                    None => OmValue::Unset,
+                }
+            }
+            OmType::TypeString => match self.x().om_str.binary_search_by_key(&(meta.slot as u32), |p| p.0) {
+                Ok(i) => OmValue::String(self.x().om_str[i].1.clone()),
+                Err(_) => OmValue::Unset,
+            },
+            _ => panic!("error in get value, kind not mapped"),
+        }
+    }
+    /// raw value of a u32 slot if it is set
+    #[inline(always)]
+    pub fn get_32(&self, slot: usize) -> Option<u32> {
+        self.om32.get(slot)
+    }
+    /// raw bits of a 64 bit slot if it is set
+    #[inline(always)]
+    pub fn get_64(&self, slot: usize) -> Option<u64> {
+        self.om64.get(slot)
+    }
+    #[inline(always)]
+    pub fn is_32_set(&self, slot: usize) -> bool {
+        self.om32.is_set(slot)
+    }
+    #[inline(always)]
+    pub fn is_64_set(&self, slot: usize) -> bool {
+        self.om64.is_set(slot)
+    }
+    /// reported as an explicit NULL
+    #[inline(always)]
+    pub fn is_32_null(&self, slot: usize) -> bool {
+        self.x.as_ref().is_some_and(|x| sorted_contains(&x.null32, slot))
+    }
+    #[inline(always)]
+    pub fn is_64_null(&self, slot: usize) -> bool {
+        self.x.as_ref().is_some_and(|x| sorted_contains(&x.null64, slot))
+    }
+
+    /// Value of u32 OM `id`, None when NULL or never reported.  Errors if the group does
+    /// not know the id or it is of another type.  Only u32, f64 and str OMs are stored,
+    /// i32, u64, i64 and f32 ones are not handled yet so have no getter.
+    pub fn get_u32(&self, group: &OmGroup, id: u32) -> Result<Option<u32>> {
+        let meta = typed_meta(group, id, TypeU32)?;
+        Ok(self.om32.get(meta.slot))
+    }
+
+    /// Value of f64 OM `id`, see `get_u32`.
+    pub fn get_f64(&self, group: &OmGroup, id: u32) -> Result<Option<f64>> {
+        let meta = typed_meta(group, id, TypeF64)?;
+        Ok(self.om64.get(meta.slot).map(f64::from_bits))
+    }
+
+    /// Value of string OM `id`, see `get_u32`.
+    pub fn get_str(&self, group: &OmGroup, id: u32) -> Result<Option<&str>> {
+        let meta = typed_meta(group, id, OmType::TypeString)?;
+        Ok(self.x().om_str.iter().find(|(slot, _)| *slot as usize == meta.slot).map(|(_, s)| s.as_str()))
+    }
+
+    /// Every OM this clutch has a value or an explicit NULL for, in id order.  Never
+    /// reported OMs are skipped.
+    pub fn oms(&self, group: &OmGroup) -> impl Iterator<Item = (u32, OmValue)> {
+        let mut v: Vec<(u32, OmValue)> = group.om_map.values()
+            .filter_map(|meta| {
+                let val = match &meta.kind {
+                    TypeU32 | TypeF64 | OmType::TypeString => self.get_value(meta),
+                    _ => OmValue::Unset,
+                };
+                match val {
+                    OmValue::Unset => None,
+                    val => Some((meta.id, val)),
+                }
+            })
+            .collect();
+        v.sort_unstable_by_key(|(id, _)| *id);
+        v.into_iter()
+    }
+
+    /// true if the u32 and f64 values are held as sorted pairs rather than by slot
+    pub fn is_sparse(&self) -> (bool, bool) {
+        (self.om32.is_sparse(), self.om64.is_sparse())
+    }
+
+    /// Re-evaluates dense vs sparse against the group's current slot counts.  Clutches
+    /// only get checked when written, so ones that stopped receiving OMs while their group
+    /// kept growing can be squeezed with this.
+    pub fn rebalance(&mut self, group: &OmGroup) {
+        self.om32.balance(group.om32_slots, group.sparse_ratio);
+        self.om64.balance(group.om64_slots, group.sparse_ratio);
+    }
+
+    #[inline(always)]
+    pub fn add_om_u32(self: &mut Self, overwrite: bool, group: &mut OmGroup, id: u32, val: u32) -> Result<()> {
+        let slot = group.find_setup_meta_slot(id, &TypeU32);
+
+        if !overwrite && (self.is_32_set(slot) || self.is_32_null(slot)) {
+            Err(anyhow!("duplicate u32 OM id: {} val: {}", id,val))
+        } else {
+            //inc_oms();
+            let changed = self.keep_prior(group, id, &TypeU32, slot, &OmValue::U32(val));
+            self.om32.put(slot, val, group.om32_slots, group.sparse_ratio);
+            if let Some(x) = self.x.as_mut() {
+                sorted_remove(&mut x.null32, slot);
+            }
+            self.wrote(group, id, changed);
+            Ok(())
+        }
+    }
+    #[inline(always)]
+    pub fn add_om_f64(self: &mut Self, overwrite: bool, group: &mut OmGroup, id: u32, val: f64) -> Result<()> {
+        let slot = group.find_setup_meta_slot(id, &TypeF64);
+
+        if !overwrite && (self.is_64_set(slot) || self.is_64_null(slot)) {
+            Err(anyhow!("duplicate f64 OM id: {} val: {}", id,val))
+        } else {
+            // inc_oms();
+            let changed = self.keep_prior(group, id, &TypeF64, slot, &OmValue::F64(val));
+            self.om64.put(slot, val.to_bits(), group.om64_slots, group.sparse_ratio);
+            if let Some(x) = self.x.as_mut() {
+                sorted_remove(&mut x.null64, slot);
+            }
+            self.wrote(group, id, changed);
+            Ok(())
+        }
+    }
+
+    /// Sets string OM `id`.  Strings keep no NULLs or history.
+    pub fn add_om_str(&mut self, overwrite: bool, group: &mut OmGroup, id: u32, val: &str) -> Result<()> {
+        if let Some(meta) = group.om_map.get(&id).filter(|m| !matches!(m.kind, OmType::TypeString)) {
+            return Err(anyhow!("OM id: {} is {}, not str", id, &meta.kind));
+        }
+        let slot = group.find_setup_meta_slot(id, &OmType::TypeString) as u32;
+        let strs = &mut self.x_mut().om_str;
+        let changed = match strs.binary_search_by_key(&slot, |p| p.0) {
+            Ok(_) if !overwrite => return Err(anyhow!("duplicate str OM id: {} val: {}", id, val)),
+            Ok(i) if strs[i].1 == val => false,
+            Ok(i) => {
+                strs[i].1 = val.to_string();
+                true
+            }
+            Err(i) => {
+                strs.insert(i, (slot, val.to_string()));
+                true
+            }
+        };
+        self.wrote(group, id, changed);
+        Ok(())
+    }
+
+    /// Records the OM as reported with a NULL value, which reads back as `OmValue::NULL`
+    /// where an OM never reported is `OmValue::Unset`.  An id new to the group is added
+    /// with `kind`, a known id keeps the kind it has.
+    pub fn set_null(&mut self, overwrite: bool, group: &mut OmGroup, id: u32, kind: &OmType) -> Result<()> {
+        let kind = group.om_map.get(&id).map_or(kind, |meta| &meta.kind).clone();
+        if !matches!(kind, TypeU32 | TypeF64) {
+            return Err(anyhow!("NULL for OM id: {} of type {} not handled, only u32 and f64 OMs take NULLs", id, &kind));
+        }
+        let slot = group.find_setup_meta_slot(id, &kind);
+        let held = match kind {
+            TypeU32 => self.is_32_set(slot) || self.is_32_null(slot),
+            _ => self.is_64_set(slot) || self.is_64_null(slot),
+        };
+        if !overwrite && held {
+            return Err(anyhow!("duplicate {} OM id: {} val: NULL", &kind, id));
+        }
+        let changed = self.keep_prior(group, id, &kind, slot, &OmValue::NULL);
+        let (vals, nulls) = match kind {
+            TypeU32 => (&mut self.om32 as &mut dyn NullableSlots, &mut self.x.get_or_insert_with(Box::default).null32),
+            _ => (&mut self.om64 as &mut dyn NullableSlots, &mut self.x.get_or_insert_with(Box::default).null64),
+        };
+        vals.unset(slot);
+        sorted_insert(nulls, slot);
+        self.wrote(group, id, changed);
+        Ok(())
+    }
+}
+
+impl OmSink for ClutchData {
+    #[inline(always)]
+    fn set_null(&mut self, overwrite: bool, group: &mut OmGroup, id: u32, kind: &OmType) -> Result<()> {
+        ClutchData::set_null(self, overwrite, group, id, kind)
+    }
+    #[inline(always)]
+    fn add_om_u32(&mut self, overwrite: bool, group: &mut OmGroup, id: u32, val: u32) -> Result<()> {
+        ClutchData::add_om_u32(self, overwrite, group, id, val)
+    }
+    #[inline(always)]
+    fn add_om_f64(&mut self, overwrite: bool, group: &mut OmGroup, id: u32, val: f64) -> Result<()> {
+        ClutchData::add_om_f64(self, overwrite, group, id, val)
+    }
+}
+
+pub fn dump(cm: &ClutchMeta, cs: &ClutchStore, first_last: bool) {
+    let mut at = 0;
+    if cs.clutches.len() == 0 { eprintln!("HEY no clutches here?"); }
+    for (ck, cd) in &cs.clutches {
+        at += 1;
+        if !first_last || at == 1 || at == cs.clutches.len() {
+            let g = cm.groups.get(ck.groupidx as usize).unwrap();
+
+            println!("{} {{ group: {} key: {}  time: {} dur: {}", at, &g.group, ck.keys.replace('\0', ", "), ck.start_iso(), ck.dur);
+            let mut non_null = 0;
+            let mut null = 0;
+            let mut unset = 0;
+            for (id, meta) in &g.om_map {
+                match cd.get_value(&meta) {
+                    OmValue::NULL => null += 1,
+                    OmValue::Unset => unset += 1,
+                    _ => non_null += 1,
+                }
+            }
+
+            print!("\tc: {}/{}/{}  ", non_null, null, unset);
+            print!("{}",
+                   &g.om_map.iter().map(|x|
+                       format!("{}:{} {}", x.0, cd.get_value(x.1), &x.1.kind)).
                        collect::<Vec<_>>().join(", "));
 
             // for (id, meta) in &g.om_map {
@@ -510,3 +2112,499 @@ pub fn dump(cm: &ClutchMeta, cs: &ClutchStore, first_last: bool) {
     }
     println!("g count: {}  g map entries {}  metas: {}", cm.groups.len(), cm.groups.len(), meta_e);
 }
+
+#[test]
+fn test_sparse_dense_switch() {
+    let mut cm = ClutchMeta::new();
+    let group = cm.find_or_new_group("wide");
+    let mut cs = ClutchStore::new();
+
+    // one clutch that carries everything so the group gets wide
+    let full = ClutchKey::new(group.idx, "full".to_string(), 1960, 900, 0);
+    let cd = cs.find_or_add_clutchdata(group, &full);
+    for id in 0..200 {
+        cd.add_om_u32(false, group, id, id).unwrap();
+        cd.add_om_f64(false, group, 10_000 + id, id as f64).unwrap();
+    }
+    assert_eq!(cd.is_sparse(), (false, false));
+
+    // a clutch with a handful of OMs in a wide group stays sparse
+    let thin = ClutchKey::new(group.idx, "thin".to_string(), 1960, 900, 0);
+    let cd = cs.find_or_add_clutchdata(group, &thin);
+    for id in (0..200).step_by(20) {
+        cd.add_om_u32(false, group, id, id * 3).unwrap();
+        cd.add_om_f64(false, group, 10_000 + id, id as f64 * 0.5).unwrap();
+    }
+    assert_eq!(cd.is_sparse(), (true, true));
+    assert!(cd.add_om_u32(false, group, 20, 1).is_err());
+    cd.add_om_u32(true, group, 20, 61).unwrap();
+    for id in 0..200 {
+        match cd.get_value(group.om_map.get(&id).unwrap()) {
+            OmValue::U32(v) => assert_eq!(v, if id == 20 { 61 } else { id * 3 }),
+            OmValue::Unset => assert!(id % 20 != 0),
+            v => panic!("unexpected {}", v),
+        }
+        match cd.get_value(group.om_map.get(&(10_000 + id)).unwrap()) {
+            OmValue::F64(v) => assert_eq!(v, id as f64 * 0.5),
+            OmValue::Unset => assert!(id % 20 != 0),
+            v => panic!("unexpected {}", v),
+        }
+    }
+
+    // filling it past the ratio flips it to dense with the values intact
+    for id in 0..100 {
+        cd.add_om_u32(true, group, id, id * 3).unwrap();
+    }
+    assert_eq!(cd.is_sparse(), (false, true));
+    assert_eq!(cd.get_32(group.om_map.get(&180).unwrap().slot), Some(540));
+
+    // and a higher ratio, more slots set before going dense, lets rebalance squeeze it back
+    group.sparse_ratio = 0.75;
+    cd.rebalance(group);
+    assert_eq!(cd.is_sparse(), (true, true));
+    assert_eq!(cd.get_32(group.om_map.get(&99).unwrap().slot), Some(297));
+    group.sparse_ratio = 0.0;
+    cd.rebalance(group);
+    assert_eq!(cd.is_sparse(), (false, false));
+    assert_eq!(cd.get_64(group.om_map.get(&10_180).unwrap().slot), Some(90f64.to_bits()));
+}
+
+#[test]
+fn test_memory_usage() {
+    let mut cm = ClutchMeta::new();
+    let mut cs = ClutchStore::new();
+    let a = cm.find_or_new_group("a");
+    for k in 0..10u32 {
+        let key = ClutchKey::new(a.idx, format!("a{}", k), 1960, 900, 0);
+        let cd = cs.find_or_add_clutchdata(a, &key);
+        for id in 0..4 {
+            cd.add_om_u32(false, a, id, k).unwrap();
+        }
+    }
+    let b = cm.find_or_new_group("b");
+    let key = ClutchKey::new(b.idx, "b".to_string(), 1960, 900, 0);
+    cs.find_or_add_clutchdata(b, &key).add_om_f64(false, b, 1, 1.5).unwrap();
+
+    let mu = cs.memory_usage(&cm);
+    assert_eq!(mu.groups.len(), 3);
+    assert_eq!(mu.groups[0].clutches, 0);
+    let ga = &mu.groups[1];
+    assert_eq!(ga.clutches, 10);
+    assert!(ga.key_bytes >= 10 * (std::mem::size_of::<ClutchKey>() + 2));
+    assert!(ga.value_bytes >= 10 * (std::mem::size_of::<ClutchData>() + 4 * 4));
+    assert!(ga.wasted_bytes >= 10 * (RESIZE_INC - 4) * 4);
+    assert!(ga.meta_bytes > 0);
+    assert_eq!(mu.groups[2].clutches, 1);
+    assert_eq!(mu.total.clutches, 11);
+    assert_eq!(mu.total.total(), mu.groups.iter().map(|g| g.total()).sum::<usize>());
+}
+
+#[test]
+fn test_budget_eviction() {
+    use std::sync::{Arc, Mutex};
+
+    let mut cm = ClutchMeta::new();
+    let group = cm.find_or_new_group("budget");
+    let mut cs = ClutchStore::new();
+    let evicted = Arc::new(Mutex::new(vec![]));
+    let ev = evicted.clone();
+    cs.on_evict(move |time, gone| ev.lock().unwrap().push((time, gone.len())));
+
+    cs.set_budget(Budget::Clutches(30));
+    for period in 0..5u64 {
+        for k in 0..10u32 {
+            let key = ClutchKey::new(group.idx, k.to_string(), period * 900, 900, 0);
+            cs.find_or_add_clutchdata(group, &key).add_om_u32(false, group, 1, k).unwrap();
+        }
+    }
+    assert_eq!(cs.len(), 30);
+    assert_eq!(cs.periods().map(|p| p.0).collect::<Vec<_>>(), vec![1800, 2700, 3600]);
+    assert_eq!(*evicted.lock().unwrap(), vec![(0, 10), (900, 10)]);
+
+    // a late clutch for a period older than all others does not push out newer data
+    let key = ClutchKey::new(group.idx, "late".to_string(), 0, 900, 0);
+    cs.find_or_add_clutchdata(group, &key);
+    assert_eq!((cs.len(), cs.budget_overflows()), (31, 1));
+
+    let per_period = cs.data_bytes() / 4;
+    cs.set_budget(Budget::Bytes(per_period * 2));
+    assert_eq!(cs.enforce_budget(), 21);
+    assert_eq!(cs.periods().collect::<Vec<_>>(), vec![(3600, 10)]);
+    assert_eq!(evicted.lock().unwrap().len(), 5);
+    // evicted periods are not closed later on
+    assert_eq!(cs.close_due(&cm, 1 << 20), vec![3600]);
+
+    cs.clear_oms();
+    assert_eq!(cs.periods().count(), 0);
+
+    // a byte budget is kept while loading, with only the newest period left over it
+    cs.set_budget(Budget::Bytes(1));
+    let group = cm.get_group_by_name("budget").unwrap();
+    for period in 0..3u64 {
+        for k in 0..10u32 {
+            let key = ClutchKey::new(group.idx, k.to_string(), period * 900, 900, 0);
+            cs.find_or_add_clutchdata(group, &key).add_om_u32(false, group, 1, 1).unwrap();
+        }
+    }
+    assert_eq!(cs.periods().map(|p| p.0).collect::<Vec<_>>(), vec![1800]);
+    // one for the late clutch above, then one per new key of a period after its first
+    assert_eq!(cs.budget_overflows(), 1 + 3 * 9);
+    // the running count is what a walk of the store finds, writes to the last clutch included
+    cs.settle_bytes();
+    assert_eq!(cs.bytes, cs.data_bytes());
+    cs.remove(&ClutchKey::new(group.idx, "3".to_string(), 1800, 900, 0)).unwrap();
+    assert_eq!(cs.bytes, cs.data_bytes());
+}
+
+#[test]
+fn test_null_vs_unset() {
+    let mut cm = ClutchMeta::new();
+    let mut cs = ClutchStore::new();
+    let group = cm.find_or_new_group("nulls");
+    let key = ClutchKey::new(group.idx, "ne1".to_string(), 1960, 900, 0);
+    let cd = cs.find_or_add_clutchdata(group, &key);
+    cd.add_om_u32(false, group, 1, 10).unwrap();
+    cd.set_null(false, group, 2, &OmType::TypeU32).unwrap();
+    cd.set_null(false, group, 3, &OmType::TypeF64).unwrap();
+    cd.add_om_f64(false, group, 4, 1.5).unwrap();
+    assert!(cd.set_null(false, group, 2, &OmType::TypeU32).is_err());
+    assert!(cd.add_om_u32(false, group, 2, 5).is_err());
+    // a known id keeps its kind
+    cd.set_null(true, group, 1, &OmType::TypeF64).unwrap();
+
+    let other = ClutchKey::new(group.idx, "ne2".to_string(), 1960, 900, 0);
+    cs.find_or_add_clutchdata(group, &other).add_om_u32(false, group, 2, 7).unwrap();
+
+    let cd = cs.get(&key).unwrap();
+    let g = cm.get_group_by_name("nulls").unwrap();
+    let vals: Vec<String> = (1..=4).map(|id| cd.get_value(g.om_map.get(&id).unwrap()).to_string()).collect();
+    assert_eq!(vals, vec!["NULL", "NULL", "NULL", "1.5"]);
+    assert!(matches!(cs.get(&other).unwrap().get_value(g.om_map.get(&1).unwrap()), OmValue::Unset));
+
+    // values and NULLs replace each other on overwrite
+    let mut cs2 = ClutchStore::new();
+    let g = cm.get_group_by_name("nulls").unwrap();
+    let cd = cs2.find_or_add_clutchdata(g, &key);
+    cd.set_null(false, g, 4, &OmType::TypeF64).unwrap();
+    cd.add_om_u32(false, g, 2, 3).unwrap();
+    cd.add_om_f64(false, g, 3, 2.5).unwrap();
+    cs.merge(&cm, &cs2, true);
+    let g = cm.get_group_by_name("nulls").unwrap();
+    let cd = cs.get(&key).unwrap();
+    assert!(matches!(cd.get_value(g.om_map.get(&2).unwrap()), OmValue::U32(3)));
+    assert!(matches!(cd.get_value(g.om_map.get(&3).unwrap()), OmValue::F64(v) if v == 2.5));
+    assert!(matches!(cd.get_value(g.om_map.get(&4).unwrap()), OmValue::NULL));
+    // a value merged over a NULL clears it, never both
+    let (s2, s3) = (g.om_map[&2].slot, g.om_map[&3].slot);
+    assert!(cd.is_32_set(s2) && !cd.is_32_null(s2));
+    assert!(cd.is_64_set(s3) && !cd.is_64_null(s3));
+
+    // NULLs of other kinds are refused before a new id gets a meta or a clutch changes
+    let g = cm.get_group_by_name("nulls").unwrap();
+    let cd = cs.find_or_add_clutchdata(g, &key);
+    cd.add_om_str(false, g, 21, "up").unwrap();
+    let before = (g.om_map.len(), g.om32_slots, g.om64_slots, g.omstr_slots, cd.oms(g).count());
+    for kind in [OmType::TypeI32, OmType::TypeU64, OmType::TypeI64, OmType::TypeF32, OmType::TypeString] {
+        let err = cd.set_null(false, g, 20, &kind).unwrap_err().to_string();
+        assert_eq!(err, format!("NULL for OM id: 20 of type {} not handled, only u32 and f64 OMs take NULLs", kind));
+    }
+    assert!(cd.set_null(true, g, 21, &OmType::TypeU32).is_err());
+    assert!(!g.om_map.contains_key(&20));
+    assert_eq!((g.om_map.len(), g.om32_slots, g.om64_slots, g.omstr_slots, cd.oms(g).count()), before);
+    assert_eq!(cd.get_str(g, 21).unwrap(), Some("up"));
+}
+
+#[test]
+fn test_typed_accessors() {
+    let mut cm = ClutchMeta::new();
+    let mut cs = ClutchStore::new();
+    let group = cm.find_or_new_group("typed");
+    let key = ClutchKey::new(group.idx, "ne1".to_string(), 1960, 900, 0);
+    let cd = cs.find_or_add_clutchdata(group, &key);
+    cd.add_om_u32(false, group, 7, 70).unwrap();
+    cd.add_om_f64(false, group, 3, 0.5).unwrap();
+    cd.set_null(false, group, 5, &OmType::TypeU32).unwrap();
+    let other = ClutchKey::new(group.idx, "ne2".to_string(), 1960, 900, 0);
+    cs.find_or_add_clutchdata(group, &other).add_om_u32(false, group, 9, 1).unwrap();
+
+    let g = cm.get_group_by_name("typed").unwrap();
+    let cd = cs.get(&key).unwrap();
+    assert_eq!(cd.get_u32(g, 7).unwrap(), Some(70));
+    assert_eq!(cd.get_f64(g, 3).unwrap(), Some(0.5));
+    assert_eq!(cd.get_u32(g, 5).unwrap(), None);
+    assert_eq!(cd.get_u32(g, 9).unwrap(), None);
+    assert!(cd.get_u32(g, 3).is_err());
+    assert!(cd.get_f64(g, 77).is_err());
+    assert!(cd.get_str(g, 7).is_err());
+    assert_eq!(cd.get_f64(g, 7).unwrap_err().to_string(), "OM id: 7 is u32 not f64");
+    let got: Vec<String> = cd.oms(g).map(|(id, v)| format!("{}={}", id, v)).collect();
+    assert_eq!(got, vec!["3=0.5", "5=NULL", "7=70"]);
+}
+
+#[test]
+fn test_remove_and_compact() {
+    let mut cm = ClutchMeta::new();
+    let mut cs = ClutchStore::new();
+    let group = cm.find_or_new_group("compact");
+    for k in 0..3u32 {
+        let key = ClutchKey::new(group.idx, k.to_string(), 1960, 900, 0);
+        let cd = cs.find_or_add_clutchdata(group, &key);
+        for id in 1..=6u32 {
+            cd.add_om_u32(false, group, id, id * 10 + k).unwrap();
+            cd.add_om_f64(false, group, 100 + id, id as f64 + 0.5).unwrap();
+        }
+        cd.set_null(false, group, 7, &OmType::TypeU32).unwrap();
+        cd.set_quality(4, QUALITY_ESTIMATED);
+        cd.set_quality(5, QUALITY_CAPPED);
+    }
+    let key0 = ClutchKey::new(group.idx, "0".to_string(), 1960, 900, 0);
+    let key2 = ClutchKey::new(group.idx, "2".to_string(), 1960, 900, 0);
+
+    let cd = cs.find_or_add_clutchdata(group, &key0);
+    assert!(cd.remove_om(group, 3));
+    assert!(!cd.remove_om(group, 3));
+    assert!(cd.remove_om(group, 7));
+    assert!(cd.get_u32(group, 3).unwrap().is_none());
+    assert!(matches!(cd.get_value(group.om_map.get(&7).unwrap()), OmValue::Unset));
+
+    assert!(cs.remove(&key2).is_some());
+    assert!(cs.remove(&key2).is_none());
+    assert_eq!(cs.len(), 2);
+    assert_eq!(cs.periods().collect::<Vec<_>>(), vec![(1960, 2)]);
+
+    let group = cm.get_group_by_name("compact").unwrap();
+    let keyn = ClutchKey::new(group.idx, "new".to_string(), 1960, 900, 0);
+    let mut other = ClutchStore::new();
+    let cd = other.find_or_add_clutchdata(group, &keyn);
+    cd.add_om_u32(false, group, 1, 1).unwrap();
+    cd.add_om_u32(false, group, 2, 2).unwrap();
+    cd.add_om_f64(false, group, 103, 3.5).unwrap();
+    cd.set_quality(2, QUALITY_CAPPED);
+    for id in [2, 5, 103] {
+        assert!(group.retire_om(id).is_some());
+    }
+    assert!(group.retire_om(2).is_none());
+    // retired OMs are not merged, nor their flags
+    assert_eq!(cs.merge(&cm, &other, false), 1);
+    assert_eq!(cs.get(&keyn).unwrap().qualities().count(), 0);
+    assert!(cs.remove(&keyn).is_some());
+    assert_eq!(cm.compact(&mut cs), 3);
+    assert_eq!(cm.compact(&mut cs), 0);
+
+    let g = cm.get_group_by_name("compact").unwrap();
+    assert_eq!((g.om32_slots, g.om64_slots), (5, 5));
+    let key1 = ClutchKey::new(g.idx, "1".to_string(), 1960, 900, 0);
+    let got: Vec<String> = cs.get(&key1).unwrap().oms(g).map(|(id, v)| format!("{}={}", id, v)).collect();
+    assert_eq!(got, vec!["1=11", "3=31", "4=41", "6=61", "7=NULL",
+                         "101=1.5", "102=2.5", "104=4.5", "105=5.5", "106=6.5"]);
+    assert_eq!(cs.get(&key1).unwrap().qualities().collect::<Vec<_>>(), vec![(4, QUALITY_ESTIMATED)]);
+    let cd = cs.get(&key0).unwrap();
+    assert!(cd.get_u32(g, 3).unwrap().is_none());
+    assert_eq!(cd.get_u32(g, 6).unwrap(), Some(60));
+}
+
+#[test]
+fn test_period_close() {
+    use std::sync::{Arc, Mutex};
+
+    let mut cm = ClutchMeta::new();
+    let mut cs = ClutchStore::new();
+    let g = cm.find_or_new_group("life");
+    let gi = g.idx;
+    cs.set_grace(std::time::Duration::from_secs(300));
+    let closed = Arc::new(Mutex::new(vec![]));
+    let c = closed.clone();
+    cs.on_close(move |cm, cs, t| {
+        // hooks see the groups of the store
+        assert_eq!(cm.get_group(gi).map(|g| g.group.as_str()), Some("life"));
+        c.lock().unwrap().push((t, cs.iter().filter(|(k, _)| k.time() == t).count()))
+    });
+    for t in [0u64, 900, 1800] {
+        for k in 0..3 {
+            let key = ClutchKey::new(g.idx, k.to_string(), t, 900, 0);
+            cs.write_clutchdata(g, &key).unwrap().add_om_u32(false, g, 1, k).unwrap();
+        }
+    }
+
+    // 0 ends at 900, closes at 1200 with the grace
+    assert!(cs.close_due(&cm, 1199).is_empty());
+    assert_eq!(cs.close_due(&cm, 2100), vec![0, 900]);
+    assert_eq!(*closed.lock().unwrap(), vec![(0, 3), (900, 3)]);
+    assert!(cs.is_closed(900) && !cs.is_closed(1800));
+
+    let g = cm.get_group_by_name("life").unwrap();
+    let late = ClutchKey::new(g.idx, "9".to_string(), 900, 900, 0);
+    let unseen = ClutchKey::new(g.idx, "9".to_string(), 450, 450, 0);
+    assert!(cs.is_late(&unseen));
+    cs.write_clutchdata(g, &late).unwrap().add_om_u32(false, g, 1, 9).unwrap();
+    assert_eq!(cs.len(), 10);
+    cs.set_late_policy(LatePolicy::Reject);
+    assert!(cs.write_clutchdata(g, &late).is_err());
+    cs.set_late_policy(LatePolicy::SideChannel);
+    cs.write_clutchdata(g, &unseen).unwrap().add_om_u32(false, g, 1, 5).unwrap();
+    let side = cs.take_late();
+    assert_eq!((side.len(), cs.len()), (1, 10));
+    assert!(cs.take_late().is_empty());
+
+    assert!(!cs.close_period(&cm, 900));
+    assert!(cs.close_period(&cm, 1800));
+    assert_eq!(closed.lock().unwrap().len(), 3);
+}
+
+#[test]
+fn test_ingest_times() {
+    let mut cm = ClutchMeta::new();
+    let mut cs = ClutchStore::new();
+    let (key, late_key) = {
+        let g = cm.find_or_new_group("ingest");
+        g.track_om_ingest = true;
+        let key = ClutchKey::new(g.idx, "ne1".to_string(), 0, 900, 0);
+        cs.set_ingest_time(1000);
+        let cd = cs.write_clutchdata(g, &key).unwrap();
+        cd.add_om_u32(false, g, 1, 10).unwrap();
+        cd.add_om_u32(false, g, 2, 20).unwrap();
+        (key, ClutchKey::new(g.idx, "ne2".to_string(), 0, 900, 0))
+    };
+    cs.close_period(&cm, 0);
+
+    // a correction and a new OM hours later, into the closed period
+    let g = cm.get_group_by_name("ingest").unwrap();
+    cs.set_ingest_time(9000);
+    let cd = cs.write_clutchdata(g, &key).unwrap();
+    cd.add_om_u32(true, g, 2, 25).unwrap();
+    cd.add_om_u32(false, g, 3, 30).unwrap();
+    cs.write_clutchdata(g, &late_key).unwrap().add_om_u32(false, g, 1, 1).unwrap();
+    assert_eq!(cs.late_arrivals(g.idx), 2);
+    assert_eq!(cs.late_arrivals(g.idx + 1), 0);
+
+    // looking a clutch up without writing is not an update
+    cs.set_ingest_time(9500);
+    cs.find_or_add_clutchdata(g, &key);
+    let cd = cs.get(&key).unwrap();
+    assert_eq!((cd.ingested(), cd.updated()), (Some(1000), Some(9000)));
+    assert_eq!((cd.om_ingested(1), cd.om_ingested(2)), (Some(1000), Some(9000)));
+    let as_of = |t: u64| -> Vec<String> {
+        cs.iter_as_of(t).flat_map(|(k, cd)| (1..=3).map(move |id| (k, cd, id)))
+            .map(|(k, cd, id)| format!("{}/{}={}", k.keys(), id, cd.get_value_as_of(&g.om_map[&id], t)))
+            .collect()
+    };
+    assert_eq!(as_of(5000), vec!["ne1/1=10", "ne1/2=UNSET", "ne1/3=UNSET"]);
+    assert_eq!(as_of(9000).len(), 6);
+    assert!(as_of(999).is_empty());
+}
+
+#[test]
+fn test_om_history() {
+    let mut cm = ClutchMeta::new();
+    let mut cs = ClutchStore::new();
+    let g = cm.find_or_new_group("audit");
+    g.track_om_ingest = true;
+    g.history_depth = 2;
+    let key = ClutchKey::new(g.idx, "ne1".to_string(), 0, 900, 0);
+    for (t, v) in [(100, Some(1)), (200, Some(2)), (300, Some(2)), (400, None), (500, Some(4))] {
+        cs.set_ingest_time(t);
+        let cd = cs.find_or_add_clutchdata(g, &key);
+        match v {
+            Some(v) => cd.add_om_u32(true, g, 1, v).unwrap(),
+            None => cd.set_null(true, g, 1, &TypeU32).unwrap(),
+        }
+        cd.add_om_f64(true, g, 2, 0.5).unwrap();
+    }
+    let cd = cs.get(&key).unwrap();
+    // rewriting the same value is not a version, only the last two are kept
+    let hist = cd.history(1).map(|v| format!("{}@{}-{}", v.value, v.written, v.replaced)).collect::<Vec<_>>();
+    assert_eq!(hist, vec!["2@200-400", "NULL@400-500"]);
+    assert_eq!(cd.history(2).count(), 0);
+    let meta = &g.om_map[&1];
+    let as_of = |t| cd.get_value_as_of(meta, t).to_string();
+    // between the first write of 2 and its rewrite, and after the rewrite
+    assert_eq!((as_of(250), as_of(350)), ("2".to_string(), "2".to_string()));
+    // 1 is past the depth kept
+    assert_eq!((as_of(150), as_of(450), as_of(600)), ("UNSET".to_string(), "NULL".to_string(), "4".to_string()));
+
+    let stamps = (cd.ingested(), cd.updated(), cd.om_ingested(1));
+    let reads = [150, 250, 350, 450, 600].map(as_of);
+
+    let mut buf = vec![];
+    crate::export::write_store(&cm, &cs, &mut buf).unwrap();
+    let (mut cm2, mut cs2) = (ClutchMeta::new(), ClutchStore::new());
+    crate::export::read_store(&mut cm2, &mut cs2, &buf[..]).unwrap();
+    let g2 = cm2.get_group_by_name("audit").unwrap();
+    let cd2 = cs2.get(&ClutchKey::new(g2.idx, "ne1".to_string(), 0, 900, 0)).unwrap();
+    assert_eq!(cd2.history(1).map(|v| v.replaced).collect::<Vec<_>>(), vec![400, 500]);
+    // as-of reads answer the same after a round trip
+    let meta2 = &g2.om_map[&1];
+    let as_of2 = |t| cd2.get_value_as_of(meta2, t).to_string();
+    assert_eq!((cd2.ingested(), cd2.updated(), cd2.om_ingested(1)), stamps);
+    assert_eq!([150, 250, 350, 450, 600].map(as_of2), reads);
+
+    let g = cm.get_group_by_name("audit").unwrap();
+    let cd = cs.find_or_add_clutchdata(g, &key);
+    cd.remove_om(g, 1);
+    assert_eq!(cd.versions().count(), 0);
+}
+
+#[test]
+fn test_key_times() {
+    let ist = FixedOffset::east_opt(5 * 3600 + 1800).unwrap();
+    let start = ist.with_ymd_and_hms(2020, 6, 1, 10, 15, 0).unwrap();
+    let key = ClutchKey::at(1, "ne1".to_string(), &start, chrono::Duration::minutes(15)).unwrap();
+    assert_eq!((key.time(), key.dur(), key.offset()), (1590986700, 900, 19800));
+    assert_eq!(key.start(), Some(start));
+    assert_eq!(key.start_utc().unwrap().to_rfc3339(), "2020-06-01T04:45:00+00:00");
+    assert_eq!(key.end().unwrap().to_rfc3339(), "2020-06-01T10:30:00+05:30");
+    assert_eq!(key.to_string(), "g:1 t:2020-06-01T10:15:00+05:30 d:900 k:ne1");
+    // 04:45 UTC and 10:15 local are neither of them on the hour
+    assert!(ClutchKey::at(1, "ne1".to_string(), &start.with_timezone(&Utc), chrono::Duration::hours(1)).is_err());
+    assert!(ClutchKey::at(1, "ne1".to_string(), &start, chrono::Duration::hours(1)).is_err());
+    let hour = ist.with_ymd_and_hms(2020, 6, 1, 10, 0, 0).unwrap();
+    assert!(ClutchKey::at(1, "ne1".to_string(), &hour, chrono::Duration::hours(1)).unwrap().is_aligned());
+    assert!(!ClutchKey::new(1, "ne1".to_string(), 1960, 900, 0).is_aligned());
+    assert_eq!(ClutchKey::new(1, "ne1".to_string(), 0, 900, 86400).start_iso(), "0");
+}
+
+#[test]
+fn test_inventory() {
+    let mut cm = ClutchMeta::new();
+    let mut cs = ClutchStore::new();
+    let path = std::env::temp_dir().join(format!("clutch_inventory_{}.txt", std::process::id()));
+    std::fs::write(&path, "# cells\nne1\\0a\nne1\\0b\n\nne2\\0a\n").unwrap();
+    let g = cm.find_or_new_group("cells");
+    assert_eq!(g.load_inventory(&path).unwrap(), 3);
+    std::fs::remove_file(&path).unwrap();
+    g.fill_missing = true;
+    for k in ["ne1\0a", "ne3\0a"] {
+        let key = ClutchKey::new(g.idx, k.to_string(), 900, 900, 3600);
+        cs.find_or_add_clutchdata(g, &key).add_om_u32(false, g, 1, 1).unwrap();
+    }
+    let gi = g.idx;
+    let other = cm.find_or_new_group("no inventory");
+    cs.find_or_add_clutchdata(other, &ClutchKey::new(other.idx, "x".to_string(), 900, 900, 0));
+    cs.find_or_add_clutchdata(other, &ClutchKey::new(other.idx, "x".to_string(), 0, 900, 0));
+    // placeholders go in over the budget rather than evict the older period
+    cs.set_budget(Budget::Clutches(4));
+    assert!(cs.close_period(&cm, 900));
+    assert_eq!(cs.key_checks(), &[KeyCheck { group: gi, time: 900, missing: vec!["ne1\0b".to_string(), "ne2\0a".to_string()],
+                                             unexpected: vec!["ne3\0a".to_string()] }]);
+    let missing = ClutchKey::new(gi, "ne1\0b".to_string(), 900, 900, 3600);
+    assert_eq!(cs.get(&missing).unwrap().suspect(), Some(SuspectReason::Missing));
+    assert_eq!(cs.len(), 6);
+    cs.set_budget(Budget::Unlimited);
+    let report = crate::completeness::completeness(&cm, &cs, gi, cm.get_group(gi).unwrap().inventory.as_ref()).unwrap();
+    assert_eq!((report[0].reported, report[0].missing.len()), (2, 2));
+
+    // reading does not fill a placeholder in, a late write does - or marks it when the
+    // write goes to the late store
+    let g = cm.get_group_by_name("cells").unwrap();
+    cs.find_or_add_clutchdata(g, &missing);
+    assert_eq!(cs.get(&missing).unwrap().suspect(), Some(SuspectReason::Missing));
+    let missing2 = ClutchKey::new(gi, "ne2\0a".to_string(), 900, 900, 3600);
+    cs.set_late_policy(LatePolicy::SideChannel);
+    cs.write_clutchdata(g, &missing2).unwrap().add_om_u32(false, g, 1, 1).unwrap();
+    assert_eq!(cs.get(&missing2).unwrap().suspect(), Some(SuspectReason::Late));
+    cs.set_late_policy(LatePolicy::Accept);
+    cs.write_clutchdata(g, &missing).unwrap().add_om_u32(false, g, 1, 1).unwrap();
+    assert_eq!(cs.get(&missing).unwrap().suspect(), None);
+    assert_eq!(cs.take_key_checks().len(), 1);
+    assert!(cs.key_checks().is_empty());
+}
@@ -0,0 +1,241 @@
+#![allow(dead_code)]
+
+use anyhow::{anyhow, Result};
+use bit_vec::BitVec;
+
+use crate::clutch::{ClutchKey, ClutchStore, OmGroup, OmMeta, OmType, OmValue};
+
+/*
+Column oriented view of one OmGroup.
+
+Each OM slot gets its own value vector plus a validity bitmap, and row N of every
+column belongs to the Nth key.  Scanning a single OM across all clutches then walks
+one contiguous vector instead of hopping through every ClutchData.
+
+Columns are created on first write and only grow as far as the last row written, so
+OMs that only a few clutches carry stay short.
+*/
+
+#[derive(Debug, Default)]
+struct Column<T> {
+    vals: Vec<T>,
+    valid: BitVec,
+}
+
+impl<T: Copy + Default> Column<T> {
+    #[inline(always)]
+    fn set(&mut self, row: usize, val: T) {
+        if self.vals.len() < row + 1 {
+            self.vals.resize(row + 1, T::default());
+            self.valid.grow(row + 1 - self.valid.len(), false);
+        }
+        self.vals[row] = val;
+        self.valid.set(row, true);
+    }
+
+    #[inline(always)]
+    fn get(&self, row: usize) -> Option<T> {
+        match self.valid.get(row) {
+            Some(true) => Some(self.vals[row]),
+            _ => None,
+        }
+    }
+
+    /// calls `f` on every valid value - whole blocks are skipped or taken at once
+    #[inline(always)]
+    fn for_each_valid<F: FnMut(T)>(&self, mut f: F) {
+        for (b, block) in self.valid.blocks().enumerate() {
+            if block == 0 {
+                continue;
+            }
+            let base = b * 32;
+            if block == u32::MAX {
+                self.vals[base..base + 32].iter().for_each(|v| f(*v));
+            } else {
+                let mut bits = block;
+                while bits != 0 {
+                    let i = bits.trailing_zeros() as usize;
+                    f(self.vals[base + i]);
+                    bits &= bits - 1;
+                }
+            }
+        }
+    }
+}
+
+/// Summary of one OM over all rows of a `ColumnGroup`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScanStats<T> {
+    pub count: usize,
+    pub sum: T,
+    pub min: Option<T>,
+    pub max: Option<T>,
+}
+
+#[derive(Debug)]
+pub struct ColumnGroup {
+    pub groupidx: u16,
+    keys: Vec<ClutchKey>,
+    cols32: Vec<Column<u32>>,
+    cols64: Vec<Column<u64>>,
+}
+
+impl ColumnGroup {
+    pub fn new(group: &OmGroup) -> ColumnGroup {
+        ColumnGroup {
+            groupidx: group.idx,
+            keys: Vec::new(),
+            cols32: Vec::new(),
+            cols64: Vec::new(),
+        }
+    }
+
+    /// Builds the columns for every clutch of `group` currently in `cs`.
+    pub fn from_store(group: &OmGroup, cs: &ClutchStore) -> ColumnGroup {
+        let mut cg = ColumnGroup::new(group);
+        cg.cols32.resize_with(group.om32_slots, Column::default);
+        cg.cols64.resize_with(group.om64_slots, Column::default);
+        for (ck, cd) in cs.iter().filter(|(ck, _)| ck.groupidx == group.idx) {
+            let row = cg.push_row(ck.clone());
+            for (slot, col) in cg.cols32.iter_mut().enumerate() {
+                if let Some(v) = cd.get_32(slot) {
+                    col.set(row, v);
+                }
+            }
+            for (slot, col) in cg.cols64.iter_mut().enumerate() {
+                if let Some(v) = cd.get_64(slot) {
+                    col.set(row, v);
+                }
+            }
+        }
+        cg
+    }
+
+    pub fn rows(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn key(&self, row: usize) -> &ClutchKey {
+        &self.keys[row]
+    }
+
+    pub fn push_row(&mut self, key: ClutchKey) -> usize {
+        self.keys.push(key);
+        self.keys.len() - 1
+    }
+
+    pub fn set_u32(&mut self, group: &mut OmGroup, row: usize, id: u32, val: u32) {
+        let slot = group.find_setup_meta_slot(id, &OmType::TypeU32);
+        if self.cols32.len() < slot + 1 {
+            self.cols32.resize_with(slot + 1, Column::default);
+        }
+        self.cols32[slot].set(row, val);
+    }
+
+    pub fn set_f64(&mut self, group: &mut OmGroup, row: usize, id: u32, val: f64) {
+        let slot = group.find_setup_meta_slot(id, &OmType::TypeF64);
+        if self.cols64.len() < slot + 1 {
+            self.cols64.resize_with(slot + 1, Column::default);
+        }
+        self.cols64[slot].set(row, val.to_bits());
+    }
+
+    pub fn get_value(&self, row: usize, meta: &OmMeta) -> OmValue {
+        match meta.kind {
+            OmType::TypeU32 => match self.cols32.get(meta.slot).and_then(|c| c.get(row)) {
+                Some(v) => OmValue::U32(v),
+                None => OmValue::Unset,
+            },
+            OmType::TypeF64 => match self.cols64.get(meta.slot).and_then(|c| c.get(row)) {
+                Some(v) => OmValue::F64(f64::from_bits(v)),
+                None => OmValue::Unset,
+            },
+            _ => panic!("error in get value, kind not mapped"),
+        }
+    }
+
+    fn meta<'a>(&self, group: &'a OmGroup, id: u32) -> Result<&'a OmMeta> {
+        if group.idx != self.groupidx {
+            return Err(anyhow!("group {} does not match columns built for group index {}", &group.group, self.groupidx));
+        }
+        group.om_map.get(&id).ok_or_else(|| anyhow!("OM id: {} not known in group {}", id, &group.group))
+    }
+
+    /// count/sum/min/max of a u32 OM over all rows
+    pub fn scan_u32(&self, group: &OmGroup, id: u32) -> Result<ScanStats<u64>> {
+        let meta = self.meta(group, id)?;
+        let mut st = ScanStats { count: 0, sum: 0u64, min: None, max: None };
+        if !matches!(meta.kind, OmType::TypeU32) {
+            return Err(anyhow!("OM id: {} is {} not u32", id, &meta.kind));
+        }
+        if let Some(col) = self.cols32.get(meta.slot) {
+            let (mut min, mut max) = (u32::MAX, 0u32);
+            col.for_each_valid(|v| {
+                st.count += 1;
+                st.sum += v as u64;
+                min = min.min(v);
+                max = max.max(v);
+            });
+            if st.count > 0 {
+                st.min = Some(min as u64);
+                st.max = Some(max as u64);
+            }
+        }
+        Ok(st)
+    }
+
+    /// count/sum/min/max of a f64 OM over all rows
+    pub fn scan_f64(&self, group: &OmGroup, id: u32) -> Result<ScanStats<f64>> {
+        let meta = self.meta(group, id)?;
+        let mut st = ScanStats { count: 0, sum: 0f64, min: None, max: None };
+        if !matches!(meta.kind, OmType::TypeF64) {
+            return Err(anyhow!("OM id: {} is {} not f64", id, &meta.kind));
+        }
+        if let Some(col) = self.cols64.get(meta.slot) {
+            let (mut min, mut max) = (f64::INFINITY, f64::NEG_INFINITY);
+            col.for_each_valid(|v| {
+                let v = f64::from_bits(v);
+                st.count += 1;
+                st.sum += v;
+                min = min.min(v);
+                max = max.max(v);
+            });
+            if st.count > 0 {
+                st.min = Some(min);
+                st.max = Some(max);
+            }
+        }
+        Ok(st)
+    }
+}
+
+#[test]
+fn test_column_scan() {
+    use crate::clutch::ClutchMeta;
+
+    let mut cm = ClutchMeta::new();
+    let group = cm.find_or_new_group("cols");
+    let mut cs = ClutchStore::new();
+    for k in 0..100u32 {
+        let key = ClutchKey::new(group.idx, k.to_string(), 1960, 900, 0);
+        let cd = cs.find_or_add_clutchdata(group, &key);
+        cd.add_om_u32(false, group, 1, k).unwrap();
+        if k % 3 == 0 {
+            cd.add_om_f64(false, group, 2, k as f64 / 2.0).unwrap();
+        }
+    }
+
+    let mut cg = ColumnGroup::from_store(group, &cs);
+    assert_eq!(cg.rows(), 100);
+    assert_eq!(cg.scan_u32(group, 1).unwrap(), ScanStats { count: 100, sum: 4950, min: Some(0), max: Some(99) });
+    let f = cg.scan_f64(group, 2).unwrap();
+    assert_eq!((f.count, f.sum, f.min, f.max), (34, 841.5, Some(0.0), Some(49.5)));
+    assert!(cg.scan_u32(group, 2).is_err());
+    assert!(cg.scan_u32(group, 77).is_err());
+
+    // maintained directly, a new OM only on the new row
+    let row = cg.push_row(ClutchKey::new(group.idx, "extra".to_string(), 1960, 900, 0));
+    cg.set_u32(group, row, 3, 7);
+    assert_eq!(cg.scan_u32(group, 3).unwrap(), ScanStats { count: 1, sum: 7, min: Some(7), max: Some(7) });
+    assert!(matches!(cg.get_value(0, group.om_map.get(&3).unwrap()), OmValue::Unset));
+}
@@ -0,0 +1,170 @@
+#![allow(dead_code)]
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{SecondsFormat, TimeZone, Utc};
+
+use crate::clutch::{ClutchData, ClutchMeta, ClutchStore, OmType, SuspectReason};
+use crate::export::unescape;
+
+/*
+Completeness of a group, period by period.
+
+Keys are checked against an expected set: a declared inventory when there is one,
+otherwise every key the group had in any earlier period - so the first period of a store
+has nothing expected and all its keys show up as unexpected (new).  OMs are counted over
+the clutches that did report: set, NULL, or never reported at all.  Placeholders for
+missing inventory keys (see `OmGroup::fill_missing`) do not count as reporting, those
+whose key reported late into a side store do.
+*/
+
+/// How many of the clutches that reported have an OM set, NULL or neither.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct OmCount {
+    pub id: u32,
+    pub set: usize,
+    pub null: usize,
+    pub unset: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct Completeness {
+    pub group: String,
+    pub time: u64,
+    /// distinct keys with a clutch in the period
+    pub reported: usize,
+    pub expected: usize,
+    /// expected keys without a clutch
+    pub missing: Vec<String>,
+    /// keys with a clutch that were not expected
+    pub unexpected: Vec<String>,
+    /// by OM id
+    pub oms: Vec<OmCount>,
+}
+
+impl Completeness {
+    /// expected keys that reported, as a fraction - 1 when nothing was expected
+    pub fn ratio(&self) -> f64 {
+        if self.expected == 0 {
+            1.0
+        } else {
+            (self.expected - self.missing.len()) as f64 / self.expected as f64
+        }
+    }
+}
+
+impl std::fmt::Display for Completeness {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let start = Utc.timestamp_opt(self.time as i64, 0).single()
+            .map_or_else(|| self.time.to_string(), |t| t.to_rfc3339_opts(SecondsFormat::Secs, true));
+        write!(f, "{} {} keys: {}/{} ({:.1}%) missing: {} unexpected: {}", &self.group, start,
+               self.expected - self.missing.len(), self.expected, self.ratio() * 100.0,
+               self.missing.len(), self.unexpected.len())?;
+        for om in &self.oms {
+            write!(f, "\n    om {}: set {} null {} unset {}", om.id, om.set, om.null, om.unset)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads a key inventory: a key per line, parts separated by an escaped \0 as in exports.
+/// Blank lines and lines starting with # are skipped.
+pub fn read_inventory(path: &Path) -> Result<BTreeSet<String>> {
+    let f = File::open(path).with_context(|| format!("cannot open inventory {}", path.display()))?;
+    let mut keys = BTreeSet::new();
+    for (n, line) in BufReader::new(f).lines().enumerate() {
+        let line = line?;
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        keys.insert(unescape(&line).with_context(|| format!("inventory {} line {}", path.display(), n + 1))?);
+    }
+    Ok(keys)
+}
+
+/// The report of every period of `group` in time order, against `inventory` if given or
+/// else the keys of the periods before.
+pub fn completeness(cm: &ClutchMeta, cs: &ClutchStore, group: u16, inventory: Option<&BTreeSet<String>>) -> Result<Vec<Completeness>> {
+    let g = cm.get_group(group).ok_or_else(|| anyhow!("no group with index {}", group))?;
+    let mut metas: Vec<_> = g.om_map.values().filter(|m| matches!(m.kind, OmType::TypeU32 | OmType::TypeF64)).collect();
+    metas.sort_unstable_by_key(|m| m.id);
+
+    let mut periods: BTreeMap<u64, BTreeMap<&str, Vec<&ClutchData>>> = BTreeMap::new();
+    let reported = cs.iter().filter(|(ck, cd)| ck.groupidx == group && cd.suspect() != Some(SuspectReason::Missing));
+    for (ck, cd) in reported {
+        periods.entry(ck.time()).or_default().entry(ck.keys()).or_default().push(cd);
+    }
+
+    let mut seen: BTreeSet<&str> = BTreeSet::new();
+    let mut out = vec![];
+    for (time, keys) in periods {
+        let (missing, unexpected, expected) = match inventory {
+            Some(inv) => (inv.iter().filter(|k| !keys.contains_key(k.as_str())).cloned().collect::<Vec<_>>(),
+                          keys.keys().filter(|k| !inv.contains(**k)).map(|k| k.to_string()).collect::<Vec<_>>(),
+                          inv.len()),
+            None => (seen.iter().filter(|k| !keys.contains_key(*k)).map(|k| k.to_string()).collect(),
+                     keys.keys().filter(|k| !seen.contains(*k)).map(|k| k.to_string()).collect(),
+                     seen.len()),
+        };
+        let mut oms: Vec<OmCount> = metas.iter().map(|m| OmCount { id: m.id, ..OmCount::default() }).collect();
+        for cd in keys.values().flatten() {
+            for (m, c) in metas.iter().zip(oms.iter_mut()) {
+                let (set, null) = match m.kind {
+                    OmType::TypeU32 => (cd.is_32_set(m.slot), cd.is_32_null(m.slot)),
+                    _ => (cd.is_64_set(m.slot), cd.is_64_null(m.slot)),
+                };
+                if set {
+                    c.set += 1;
+                } else if null {
+                    c.null += 1;
+                } else {
+                    c.unset += 1;
+                }
+            }
+        }
+        out.push(Completeness { group: g.group.clone(), time, reported: keys.len(), expected, missing, unexpected, oms });
+        seen.extend(keys.keys());
+    }
+    Ok(out)
+}
+
+#[test]
+fn test_completeness() {
+    use crate::clutch::ClutchKey;
+
+    let mut cm = ClutchMeta::new();
+    let mut cs = ClutchStore::new();
+    let g = cm.find_or_new_group("cells");
+    let reports: [(u64, &[&str]); 3] = [(0, &["a", "b", "c"]), (900, &["a", "c", "d"]), (1800, &["a", "b", "c", "d"])];
+    for (t, keys) in reports.iter() {
+        for k in keys.iter() {
+            let key = ClutchKey::new(g.idx, k.to_string(), *t, 900, 0);
+            let cd = cs.find_or_add_clutchdata(g, &key);
+            cd.add_om_u32(false, g, 1, 1).unwrap();
+            match *k {
+                "a" => cd.set_null(false, g, 2, &OmType::TypeF64).unwrap(),
+                "b" => {}
+                _ => cd.add_om_f64(false, g, 2, 0.5).unwrap(),
+            }
+        }
+    }
+    let gi = g.idx;
+    let got = completeness(&cm, &cs, gi, None).unwrap();
+    let brief = |r: &Completeness| format!("{} {}/{} -{:?} +{:?}", r.time, r.reported, r.expected, r.missing, r.unexpected);
+    assert_eq!(got.iter().map(brief).collect::<Vec<_>>(), vec![
+        r#"0 3/0 -[] +["a", "b", "c"]"#,
+        r#"900 3/3 -["b"] +["d"]"#,
+        r#"1800 4/4 -[] +[]"#,
+    ]);
+    assert_eq!(got[2].oms, vec![OmCount { id: 1, set: 4, null: 0, unset: 0 }, OmCount { id: 2, set: 2, null: 1, unset: 1 }]);
+    assert_eq!(got[1].to_string().lines().next().unwrap(), "cells 1970-01-01T00:15:00Z keys: 2/3 (66.7%) missing: 1 unexpected: 1");
+
+    let inv: BTreeSet<String> = ["a", "b", "e"].iter().map(|s| s.to_string()).collect();
+    let got = completeness(&cm, &cs, gi, Some(&inv)).unwrap();
+    assert_eq!(brief(&got[0]), r#"0 3/3 -["e"] +["c"]"#);
+    assert!(completeness(&cm, &cs, gi + 1, None).is_err());
+}
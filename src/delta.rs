@@ -0,0 +1,216 @@
+#![allow(dead_code)]
+
+use fnv::FnvHashSet;
+
+use crate::clutch::{ClutchData, ClutchKey, ClutchMeta, ClutchStore, OmGroup, OmType, QUALITY_SUSPECT};
+
+/*
+Deltas of cumulative u32 counters between consecutive periods.
+
+A counter that went down either wrapped past 2^32 or the element restarted and the
+counter began again from zero.  The two cannot be told apart for sure, so a drop is
+taken as a wrap only when the wrapped delta is small enough to be believable, otherwise
+it is a reset and the new value is the delta.  Anything flagged suspect is written as an
+explicit NULL rather than guessed at.
+
+Suspect clutches make their deltas suspect, and the quality flags of both counter values
+carry over to the delta, with resets also marked QUALITY_SUSPECT.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeltaFlag {
+    /// counter went past u32::MAX and started again
+    Wrapped,
+    /// counter dropped too far to be a wrap - taken as restarted from zero
+    Reset,
+    /// delta was not believable, output is NULL
+    Suspect,
+}
+
+#[derive(Debug, Clone)]
+pub struct DeltaOpts {
+    /// largest delta still taken as a wrap when a counter goes down
+    pub max_wrap_delta: u64,
+    /// any delta above this is suspect
+    pub max_delta: Option<u64>,
+    /// resets output NULL (flagged suspect) instead of the new counter value
+    pub reset_as_null: bool,
+    /// only these ids are counters, None means every u32 OM in the group
+    pub counters: Option<FnvHashSet<u32>>,
+}
+
+impl Default for DeltaOpts {
+    fn default() -> Self {
+        DeltaOpts {
+            max_wrap_delta: 1 << 30,
+            max_delta: None,
+            reset_as_null: false,
+            counters: None,
+        }
+    }
+}
+
+/// An OM whose delta was not a plain `cur - prev`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeltaNote {
+    pub key: ClutchKey,
+    pub id: u32,
+    pub prev: u32,
+    pub cur: u32,
+    pub flag: DeltaFlag,
+}
+
+/// The delta of one counter and any flag that goes with it - None means NULL.
+pub fn counter_delta(prev: u32, cur: u32, opts: &DeltaOpts) -> (Option<u64>, Option<DeltaFlag>) {
+    let (d, flag) = if cur >= prev {
+        ((cur - prev) as u64, None)
+    } else {
+        let wrapped = (u32::MAX - prev) as u64 + cur as u64 + 1;
+        if wrapped <= opts.max_wrap_delta {
+            (wrapped, Some(DeltaFlag::Wrapped))
+        } else if opts.reset_as_null {
+            return (None, Some(DeltaFlag::Suspect));
+        } else {
+            (cur as u64, Some(DeltaFlag::Reset))
+        }
+    };
+    match opts.max_delta {
+        Some(max) if d > max => (None, Some(DeltaFlag::Suspect)),
+        _ => (Some(d), flag),
+    }
+}
+
+fn counter_slots(group: &OmGroup, opts: &DeltaOpts) -> Vec<(u32, usize)> {
+    let mut v: Vec<(u32, usize)> = group.om_map.values()
+        .filter(|m| matches!(m.kind, OmType::TypeU32))
+        .filter(|m| opts.counters.as_ref().is_none_or(|c| c.contains(&m.id)))
+        .map(|m| (m.id, m.slot))
+        .collect();
+    v.sort_unstable();
+    v
+}
+
+/// Writes the deltas between `prev` and `cur` into `out`.  Deltas wider than u32 (only
+/// possible with a `max_wrap_delta` or `max_delta` above u32::MAX) are suspect.
+pub fn delta_clutch(group: &mut OmGroup, key: &ClutchKey, prev: &ClutchData, cur: &ClutchData,
+                    out: &mut ClutchData, opts: &DeltaOpts, notes: &mut Vec<DeltaNote>) {
+    out.inherit_suspect(prev);
+    out.inherit_suspect(cur);
+    for (id, slot) in counter_slots(group, opts) {
+        let (p, c) = match (prev.get_32(slot), cur.get_32(slot)) {
+            (Some(p), Some(c)) => (p, c),
+            _ => continue,
+        };
+        let (d, mut flag) = counter_delta(p, c, opts);
+        match d {
+            Some(d) if d <= u32::MAX as u64 => {
+                out.add_om_u32(true, group, id, d as u32).expect("overwrite of delta cannot fail");
+                let q = prev.quality(id) | cur.quality(id)
+                    | if flag == Some(DeltaFlag::Reset) { QUALITY_SUSPECT } else { 0 };
+                out.add_quality(id, q);
+            }
+            Some(_) => flag = Some(DeltaFlag::Suspect),
+            None => {}
+        }
+        if flag == Some(DeltaFlag::Suspect) {
+            out.set_null(true, group, id, &OmType::TypeU32).expect("overwrite of delta cannot fail");
+        }
+        if let Some(flag) = flag {
+            notes.push(DeltaNote { key: key.clone(), id, prev: p, cur: c, flag });
+        }
+    }
+}
+
+/// Deltas for every key that has clutches in back to back periods (`time` of one is
+/// `time + dur` of the one before).  The new store has a clutch per later period under
+/// the same key and group, along with the notes of every wrapped/reset/suspect OM.
+pub fn delta_store(cm: &mut ClutchMeta, cs: &ClutchStore, opts: &DeltaOpts) -> (ClutchStore, Vec<DeltaNote>) {
+    let mut out = ClutchStore::new();
+    let mut notes = vec![];
+    let mut last: Option<(&ClutchKey, &ClutchData)> = None;
+    for (ck, cd) in cs.iter() {
+        if let Some((pk, pd)) = last {
+            if pk.keys() == ck.keys() && pk.groupidx == ck.groupidx
+                && pk.time() + pk.dur() as u64 == ck.time() {
+                let group = cm.get_group_by_idx(ck.groupidx).expect("clutch group not in meta");
+                let od = out.find_or_add_clutchdata(group, ck);
+                delta_clutch(group, ck, pd, cd, od, opts, &mut notes);
+            }
+        }
+        last = Some((ck, cd));
+    }
+    (out, notes)
+}
+
+#[test]
+fn test_counter_deltas() {
+    use crate::clutch::OmValue;
+
+    let opts = DeltaOpts::default();
+    assert_eq!(counter_delta(10, 25, &opts), (Some(15), None));
+    assert_eq!(counter_delta(u32::MAX - 4, 5, &opts), (Some(10), Some(DeltaFlag::Wrapped)));
+    assert_eq!(counter_delta(1_000_000, 7, &opts), (Some(7), Some(DeltaFlag::Reset)));
+    let strict = DeltaOpts { max_delta: Some(100), reset_as_null: true, ..DeltaOpts::default() };
+    assert_eq!(counter_delta(1_000_000, 7, &strict), (None, Some(DeltaFlag::Suspect)));
+    assert_eq!(counter_delta(0, 101, &strict), (None, Some(DeltaFlag::Suspect)));
+
+    let mut cm = ClutchMeta::new();
+    let mut cs = ClutchStore::new();
+    let group = cm.find_or_new_group("ctr");
+    let samples: [(u64, u32); 4] = [(0, 100), (900, 250), (1800, u32::MAX - 9), (2700, 40)];
+    for (t, v) in samples.iter() {
+        let key = ClutchKey::new(group.idx, "ne1".to_string(), *t, 900, 0);
+        let cd = cs.find_or_add_clutchdata(group, &key);
+        cd.add_om_u32(false, group, 1, *v).unwrap();
+        cd.add_om_u32(false, group, 2, 5).unwrap();
+        cd.add_om_f64(false, group, 3, 1.0).unwrap();
+    }
+    // a gap - no delta for the period after it
+    let key = ClutchKey::new(group.idx, "ne1".to_string(), 9000, 900, 0);
+    cs.find_or_add_clutchdata(group, &key).add_om_u32(false, group, 1, 50).unwrap();
+
+    let (ds, notes) = delta_store(&mut cm, &cs, &opts);
+    let group = cm.get_group_by_name("ctr").unwrap();
+    assert_eq!(ds.len(), 3);
+    let got: Vec<(u64, String, String)> = ds.iter()
+        .map(|(k, d)| (k.time(),
+                       d.get_value(group.om_map.get(&1).unwrap()).to_string(),
+                       d.get_value(group.om_map.get(&2).unwrap()).to_string()))
+        .collect();
+    assert_eq!(got, vec![
+        (900, "150".to_string(), "0".to_string()),
+        (1800, (u32::MAX - 9 - 250).to_string(), "0".to_string()),
+        (2700, "50".to_string(), "0".to_string()),
+    ]);
+    assert_eq!(notes.len(), 1);
+    assert_eq!((notes[0].key.time(), notes[0].id, notes[0].flag), (2700, 1, DeltaFlag::Wrapped));
+    assert!(matches!(ds.iter().next().unwrap().1.get_value(group.om_map.get(&3).unwrap()), OmValue::Unset));
+}
+
+#[test]
+fn test_delta_flags_propagate() {
+    use crate::clutch::{SuspectReason, QUALITY_ESTIMATED};
+
+    let mut cm = ClutchMeta::new();
+    let group = cm.find_or_new_group("ctr");
+    let mut prev = ClutchStore::new();
+    let key0 = ClutchKey::new(group.idx, "ne1".to_string(), 0, 900, 0);
+    let key1 = ClutchKey::new(group.idx, "ne1".to_string(), 900, 900, 0);
+    let p = prev.find_or_add_clutchdata(group, &key0);
+    p.add_om_u32(false, group, 1, 2_000_000_000).unwrap();
+    p.add_om_u32(false, group, 2, 10).unwrap();
+    p.set_quality(2, QUALITY_ESTIMATED);
+    let mut cur = ClutchStore::new();
+    let c = cur.find_or_add_clutchdata(group, &key1);
+    c.add_om_u32(false, group, 1, 5).unwrap();
+    c.add_om_u32(false, group, 2, 20).unwrap();
+    c.set_suspect(SuspectReason::ClockChange);
+    prev.merge(&cm, &cur, false);
+
+    let (ds, notes) = delta_store(&mut cm, &prev, &DeltaOpts::default());
+    assert_eq!(notes.len(), 1);
+    assert_eq!(notes[0].flag, DeltaFlag::Reset);
+    let d = ds.get(&key1).unwrap();
+    assert_eq!(d.suspect(), Some(SuspectReason::Propagated));
+    assert_eq!(d.qualities().collect::<Vec<_>>(), vec![(1, QUALITY_SUSPECT), (2, QUALITY_ESTIMATED)]);
+}
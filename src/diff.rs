@@ -0,0 +1,173 @@
+#![allow(dead_code)]
+
+use std::cmp::Ordering;
+use std::io::Write;
+
+use anyhow::Result;
+
+use crate::clutch::{ClutchData, ClutchKey, ClutchMeta, ClutchStore, OmGroup, OmValue};
+
+/// How close two f64 values must be to count as the same.  Values match when they are
+/// within either tolerance, so both at 0 means exact.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DiffOpts {
+    pub abs_tol: f64,
+    /// relative to the larger magnitude of the two
+    pub rel_tol: f64,
+}
+
+impl Default for DiffOpts {
+    fn default() -> Self {
+        DiffOpts { abs_tol: 0.0, rel_tol: 1e-9 }
+    }
+}
+
+/// One OM of a clutch found in both stores that does not match.
+#[derive(Debug)]
+pub struct OmDiff {
+    pub key: ClutchKey,
+    pub id: u32,
+    pub a: OmValue,
+    pub b: OmValue,
+}
+
+#[derive(Debug, Default)]
+pub struct StoreDiff {
+    pub only_a: Vec<ClutchKey>,
+    pub only_b: Vec<ClutchKey>,
+    pub changed: Vec<OmDiff>,
+    /// clutches in both stores with every OM matching
+    pub same: usize,
+}
+
+pub fn f64_matches(a: f64, b: f64, opts: &DiffOpts) -> bool {
+    if a == b || (a.is_nan() && b.is_nan()) {
+        return true;
+    }
+    let d = (a - b).abs();
+    d <= opts.abs_tol || d <= opts.rel_tol * a.abs().max(b.abs())
+}
+
+fn value_matches(a: &OmValue, b: &OmValue, opts: &DiffOpts) -> bool {
+    match (a, b) {
+        (OmValue::U32(a), OmValue::U32(b)) => a == b,
+        (OmValue::F64(a), OmValue::F64(b)) => f64_matches(*a, *b, opts),
+        (OmValue::String(a), OmValue::String(b)) => a == b,
+        (OmValue::NULL, OmValue::NULL) | (OmValue::Unset, OmValue::Unset) => true,
+        _ => false,
+    }
+}
+
+/// adds the mismatching OMs of one clutch to `out`, returning how many there were
+fn diff_clutch(group: &OmGroup, key: &ClutchKey, a: &ClutchData, b: &ClutchData,
+               opts: &DiffOpts, out: &mut Vec<OmDiff>) -> usize {
+    let mut ids: Vec<u32> = group.om_map.keys().cloned().collect();
+    ids.sort_unstable();
+    let before = out.len();
+    for id in ids {
+        let meta = &group.om_map[&id];
+        let (va, vb) = (a.get_value(meta), b.get_value(meta));
+        if !value_matches(&va, &vb, opts) {
+            out.push(OmDiff { key: key.clone(), id, a: va, b: vb });
+        }
+    }
+    out.len() - before
+}
+
+/// Compares two stores built against the same `ClutchMeta` (read both exports into one
+/// meta so group indexes and slots agree).  Clutches are matched by their full key.
+pub fn diff(cm: &ClutchMeta, a: &ClutchStore, b: &ClutchStore, opts: &DiffOpts) -> StoreDiff {
+    let mut d = StoreDiff::default();
+    let mut ia = a.iter().peekable();
+    let mut ib = b.iter().peekable();
+    loop {
+        let ord = match (ia.peek(), ib.peek()) {
+            (None, None) => break,
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (Some((ka, _)), Some((kb, _))) => ka.cmp(kb),
+        };
+        match ord {
+            Ordering::Less => d.only_a.push(ia.next().unwrap().0.clone()),
+            Ordering::Greater => d.only_b.push(ib.next().unwrap().0.clone()),
+            Ordering::Equal => {
+                let (key, ca) = ia.next().unwrap();
+                let (_, cb) = ib.next().unwrap();
+                let group = cm.get_group(key.groupidx).expect("clutch group not in meta");
+                if diff_clutch(group, key, ca, cb, opts, &mut d.changed) == 0 {
+                    d.same += 1;
+                }
+            }
+        }
+    }
+    d
+}
+
+impl StoreDiff {
+    pub fn is_empty(&self) -> bool {
+        self.only_a.is_empty() && self.only_b.is_empty() && self.changed.is_empty()
+    }
+
+    /// Line per difference then a summary line.
+    pub fn write<W: Write>(&self, cm: &ClutchMeta, mut w: W) -> Result<()> {
+        let key = |k: &ClutchKey| format!("{} [{}] time: {} dur: {}",
+                                          cm.get_group(k.groupidx).map_or("?", |g| g.group.as_str()),
+                                          k.keys().replace('\0', ", "), k.start_iso(), k.dur());
+        for k in &self.only_a {
+            writeln!(w, "only in A: {}", key(k))?;
+        }
+        for k in &self.only_b {
+            writeln!(w, "only in B: {}", key(k))?;
+        }
+        for c in &self.changed {
+            writeln!(w, "changed: {} id: {} A: {} B: {}", key(&c.key), c.id, c.a, c.b)?;
+        }
+        let keys = self.changed.iter().map(|c| &c.key).collect::<std::collections::BTreeSet<_>>().len();
+        writeln!(w, "only in A: {}  only in B: {}  changed clutches: {}  changed OMs: {}  same: {}",
+                 self.only_a.len(), self.only_b.len(), keys, self.changed.len(), self.same)?;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_store_diff() {
+    let mut cm = ClutchMeta::new();
+    let g = cm.find_or_new_group("d");
+    let mut a = ClutchStore::new();
+    let mut b = ClutchStore::new();
+    for k in 0..4u32 {
+        let key = ClutchKey::new(g.idx, k.to_string(), 1960, 900, 0);
+        if k != 3 {
+            let cd = a.find_or_add_clutchdata(g, &key);
+            cd.add_om_u32(false, g, 1, k).unwrap();
+            cd.add_om_f64(false, g, 2, 100.0).unwrap();
+            cd.add_om_str(false, g, 3, "v1").unwrap();
+        }
+        if k != 0 {
+            let cd = b.find_or_add_clutchdata(g, &key);
+            cd.add_om_u32(false, g, 1, if k == 2 { 99 } else { k }).unwrap();
+            cd.add_om_f64(false, g, 2, if k == 1 { 100.0 + 1e-10 } else { 100.5 }).unwrap();
+            cd.add_om_str(false, g, 3, if k == 2 { "v2" } else { "v1" }).unwrap();
+        }
+    }
+
+    let d = diff(&cm, &a, &b, &DiffOpts::default());
+    assert_eq!(d.only_a.iter().map(|k| k.keys()).collect::<Vec<_>>(), vec!["0"]);
+    assert_eq!(d.only_b.iter().map(|k| k.keys()).collect::<Vec<_>>(), vec!["3"]);
+    let got: Vec<String> = d.changed.iter().map(|c| format!("{}/{}: {} {}", c.key.keys(), c.id, c.a, c.b)).collect();
+    assert_eq!(got, vec!["2/1: 2 99", "2/2: 100 100.5", "2/3: v1 v2"]);
+    assert_eq!(d.same, 1);
+
+    let d = diff(&cm, &a, &b, &DiffOpts { abs_tol: 0.5, rel_tol: 0.0 });
+    assert_eq!(d.changed.len(), 2);
+    let mut out = vec![];
+    d.write(&cm, &mut out).unwrap();
+    assert!(String::from_utf8(out).unwrap().ends_with("changed clutches: 1  changed OMs: 2  same: 1\n"));
+    assert!(diff(&cm, &a, &a, &DiffOpts::default()).is_empty());
+    crate::clutch::dump(&cm, &a, false);
+
+    // strings merge like the rest, after which b has nothing a lacks
+    a.merge(&cm, &b, true);
+    let d = diff(&cm, &a, &b, &DiffOpts::default());
+    assert!(d.changed.is_empty() && d.only_b.is_empty());
+}
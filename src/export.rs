@@ -0,0 +1,292 @@
+#![allow(dead_code)]
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use std::convert::TryFrom;
+
+use anyhow::{anyhow, Context, Result};
+use chrono::DateTime;
+
+use crate::clutch::{ClutchKey, ClutchMeta, ClutchStore, OmGroup, OmType, OmValue, OmVersion, SuspectReason};
+
+/*
+Plain text export of a store, one record per line with tab separated fields:
+
+    #clutch-export 2
+    group   <name>  <id>:<kind>,<id>:<kind>,...
+    clutch  <group> <keys>  <start>  <dur>  <offset>  <suspect code or ->  <ingested>  <updated>
+    om      <id>    <kind>  <value or NULL>  <quality bits>  <ingested>
+    hist    <id>    <kind>  <value or NULL>  <written>  <replaced>
+
+Group lines list every OM the group knows in slot order so an import lays the slots out
+the same way.  OM lines belong to the clutch line before them and only OMs the clutch
+has a value or NULL for are written.  Hist lines follow them with the values overwrites
+replaced, for groups keeping history, oldest first per OM.  Ingest times are epoch
+seconds, 0 where they were not tracked, and an import without them reads them as 0 -
+so as-of-ingest reads of an imported store answer as the exported one did.
+
+The start is ISO-8601 with the element's offset (2020-06-01T10:15:00+02:00), dur is in
+seconds and offset in seconds east of UTC.  Version 1 wrote the start as seconds since
+the epoch, which is still read.  Key parts are kept \0 separated and, like anything
+else that could break a line, backslash escaped.  f64 values are written in Rust's
+shortest form that parses back to the same bits.  str values are escaped and never NULL,
+so a str OM reading NULL is that text.
+*/
+
+const HEADER: &str = "#clutch-export 2";
+const HEADER_V1: &str = "#clutch-export 1";
+
+pub fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\t' => out.push_str("\\t"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\0' => out.push_str("\\0"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+pub fn unescape(s: &str) -> Result<String> {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => out.push('\\'),
+            Some('t') => out.push('\t'),
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some('0') => out.push('\0'),
+            o => return Err(anyhow!("bad escape \\{} in {}", o.map(String::from).unwrap_or_default(), s)),
+        }
+    }
+    Ok(out)
+}
+
+fn kind_name(kind: &OmType) -> &'static str {
+    match kind {
+        OmType::TypeU32 => "u32",
+        OmType::TypeF64 => "f64",
+        OmType::TypeString => "str",
+        _ => "unknown",
+    }
+}
+
+fn parse_kind(s: &str) -> Result<OmType> {
+    match s {
+        "u32" => Ok(OmType::TypeU32),
+        "f64" => Ok(OmType::TypeF64),
+        "str" => Ok(OmType::TypeString),
+        _ => Err(anyhow!("OM type {} cannot be imported", s)),
+    }
+}
+
+fn write_group<W: Write>(w: &mut W, g: &OmGroup) -> Result<()> {
+    let mut metas: Vec<_> = g.om_map.values().collect();
+    metas.sort_by_key(|m| (kind_name(&m.kind), m.slot));
+    let oms = metas.iter().map(|m| format!("{}:{}", m.id, kind_name(&m.kind))).collect::<Vec<_>>().join(",");
+    writeln!(w, "group\t{}\t{}", escape(&g.group), oms)?;
+    Ok(())
+}
+
+/// Writes every group of `cm` and every clutch of `cs` in key order.
+pub fn write_store<W: Write>(cm: &ClutchMeta, cs: &ClutchStore, mut w: W) -> Result<()> {
+    writeln!(w, "{}", HEADER)?;
+    for g in cm.groups() {
+        write_group(&mut w, g)?;
+    }
+    for (ck, cd) in cs.iter() {
+        let g = cm.get_group(ck.groupidx).ok_or_else(|| anyhow!("clutch group {} not in meta", ck.groupidx))?;
+        let suspect = cd.suspect().map_or("-".to_string(), |r| r.code().to_string());
+        writeln!(w, "clutch\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}", escape(&g.group), escape(ck.keys()),
+                 ck.start_iso(), ck.dur(), ck.offset(), suspect, cd.ingested().unwrap_or(0), cd.updated().unwrap_or(0))?;
+        for (id, val) in cd.oms(g) {
+            let kind = &g.om_map[&id].kind;
+            let val = match val {
+                OmValue::String(s) => escape(&s),
+                v => v.to_string(),
+            };
+            writeln!(w, "om\t{}\t{}\t{}\t{}\t{}", id, kind_name(kind), val, cd.quality(id), cd.om_ingested(id).unwrap_or(0))?;
+        }
+        for (id, v) in cd.versions() {
+            writeln!(w, "hist\t{}\t{}\t{}\t{}\t{}", id, kind_name(&g.om_map[&id].kind), v.value, v.written, v.replaced)?;
+        }
+    }
+    w.flush()?;
+    Ok(())
+}
+
+pub fn write_file(cm: &ClutchMeta, cs: &ClutchStore, path: &Path) -> Result<()> {
+    let f = File::create(path).with_context(|| format!("cannot create export file {}", path.display()))?;
+    write_store(cm, cs, BufWriter::new(f)).with_context(|| format!("writing export file {}", path.display()))
+}
+
+/// seconds since the epoch from an ISO-8601 start, or from the bare seconds of version 1
+fn parse_start(s: &str) -> Result<u64> {
+    match s.parse() {
+        Ok(t) => Ok(t),
+        Err(_) => {
+            let t = DateTime::parse_from_rfc3339(s).with_context(|| format!("bad period start {}", s))?;
+            u64::try_from(t.timestamp()).map_err(|_| anyhow!("period start {} is before the epoch", s))
+        }
+    }
+}
+
+/// an optional trailing number, 0 when missing
+fn opt_field(fields: &[&str], i: usize) -> Result<u64> {
+    fields.get(i).map_or(Ok(0), |f| f.parse().with_context(|| format!("bad field {}", i + 1)))
+}
+
+fn field<'a>(fields: &[&'a str], i: usize) -> Result<&'a str> {
+    fields.get(i).copied().ok_or_else(|| anyhow!("missing field {}", i + 1))
+}
+
+/// Reads an export into `cs`, creating groups and OM ids in `cm` as needed (groups are
+/// matched by name).  Values already in `cs` are overwritten.  Returns the number of
+/// clutch records read.
+pub fn read_store<R: BufRead>(cm: &mut ClutchMeta, cs: &mut ClutchStore, r: R) -> Result<usize> {
+    let mut clutches = 0;
+    let mut cur: Option<ClutchKey> = None;
+    for (n, line) in r.lines().enumerate() {
+        let line = line?;
+        if n == 0 {
+            if line != HEADER && line != HEADER_V1 {
+                return Err(anyhow!("not a clutch export, first line is: {}", line));
+            }
+            continue;
+        }
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        (|| -> Result<()> {
+            match fields[0] {
+                "group" => {
+                    let g = cm.find_or_new_group(&unescape(field(&fields, 1)?)?);
+                    for om in field(&fields, 2)?.split(',').filter(|s| !s.is_empty()) {
+                        let (id, kind) = om.split_once(':').ok_or_else(|| anyhow!("bad OM {}", om))?;
+                        let id: u32 = id.parse()?;
+                        let kind = parse_kind(kind)?;
+                        if !g.om_map.contains_key(&id) {
+                            g.find_setup_meta_slot(id, &kind);
+                        }
+                    }
+                }
+                "clutch" => {
+                    let g = cm.find_or_new_group(&unescape(field(&fields, 1)?)?);
+                    let key = ClutchKey::new(g.idx, unescape(field(&fields, 2)?)?, parse_start(field(&fields, 3)?)?,
+                                             field(&fields, 4)?.parse()?, field(&fields, 5)?.parse()?);
+                    let cd = cs.find_or_add_clutchdata(g, &key);
+                    match field(&fields, 6)? {
+                        "-" => cd.clear_suspect(),
+                        c => cd.set_suspect(SuspectReason::from_code(c.parse()?)),
+                    }
+                    cd.restore_ingest(opt_field(&fields, 7)?, opt_field(&fields, 8)?);
+                    cur = Some(key);
+                    clutches += 1;
+                }
+                "om" => {
+                    let key = cur.as_ref().ok_or_else(|| anyhow!("OM before any clutch"))?;
+                    let g = cm.get_group_by_idx(key.groupidx).unwrap();
+                    let id: u32 = field(&fields, 1)?.parse()?;
+                    let kind = parse_kind(field(&fields, 2)?)?;
+                    if let Some(meta) = g.om_map.get(&id) {
+                        if kind_name(&meta.kind) != kind_name(&kind) {
+                            return Err(anyhow!("OM id: {} is {} in group {}", id, &meta.kind, &g.group));
+                        }
+                    }
+                    let cd = cs.find_or_add_clutchdata(g, key);
+                    match (field(&fields, 3)?, &kind) {
+                        (v, OmType::TypeString) => cd.add_om_str(true, g, id, &unescape(v)?)?,
+                        ("NULL", _) => cd.set_null(true, g, id, &kind)?,
+                        (v, OmType::TypeU32) => cd.add_om_u32(true, g, id, v.parse()?)?,
+                        (v, _) => cd.add_om_f64(true, g, id, v.parse()?)?,
+                    }
+                    cd.set_quality(id, field(&fields, 4)?.parse()?);
+                    cd.restore_om_ingested(id, opt_field(&fields, 5)?);
+                }
+                "hist" => {
+                    let key = cur.as_ref().ok_or_else(|| anyhow!("history before any clutch"))?;
+                    let g = cm.get_group_by_idx(key.groupidx).unwrap();
+                    let id: u32 = field(&fields, 1)?.parse()?;
+                    let kind = parse_kind(field(&fields, 2)?)?;
+                    if g.om_map.get(&id).is_none_or(|m| kind_name(&m.kind) != kind_name(&kind)) {
+                        return Err(anyhow!("history of OM id: {} which group {} has no {} for", id, &g.group, kind_name(&kind)));
+                    }
+                    let value = match (field(&fields, 3)?, &kind) {
+                        ("NULL", _) => OmValue::NULL,
+                        (v, OmType::TypeU32) => OmValue::U32(v.parse()?),
+                        (v, _) => OmValue::F64(v.parse()?),
+                    };
+                    let version = OmVersion { value, written: field(&fields, 4)?.parse()?, replaced: field(&fields, 5)?.parse()? };
+                    // keep everything exported, later overwrites bound it to the group's depth
+                    cs.find_or_add_clutchdata(g, key).push_version(usize::MAX, id, version);
+                }
+                t => return Err(anyhow!("unknown record type {}", t)),
+            }
+            Ok(())
+        })().with_context(|| format!("export line {}", n + 1))?;
+    }
+    Ok(clutches)
+}
+
+pub fn read_file(cm: &mut ClutchMeta, cs: &mut ClutchStore, path: &Path) -> Result<usize> {
+    let f = File::open(path).with_context(|| format!("cannot open export file {}", path.display()))?;
+    read_store(cm, cs, BufReader::new(f)).with_context(|| format!("reading export file {}", path.display()))
+}
+
+#[test]
+fn test_export_round_trip() {
+    use crate::clutch::QUALITY_ESTIMATED;
+
+    let mut cm = ClutchMeta::new();
+    let mut cs = ClutchStore::new();
+    let g = cm.find_or_new_group("cell\tstats");
+    for k in 0..3u32 {
+        let key = ClutchKey::new(g.idx, format!("ne{}\0cell\\{}", k, k), 1960, 900, -3600);
+        let cd = cs.find_or_add_clutchdata(g, &key);
+        cd.add_om_u32(false, g, 5, k).unwrap();
+        cd.add_om_f64(false, g, 6, 0.1 + k as f64).unwrap();
+        cd.add_om_str(false, g, 8, &format!("sw\t{}", k)).unwrap();
+        if k == 1 {
+            cd.set_null(false, g, 7, &OmType::TypeU32).unwrap();
+            cd.set_suspect(SuspectReason::ClockChange);
+            cd.set_quality(6, QUALITY_ESTIMATED);
+        }
+    }
+    let mut buf = vec![];
+    write_store(&cm, &cs, &mut buf).unwrap();
+
+    let mut cm2 = ClutchMeta::new();
+    cm2.find_or_new_group("other");
+    let mut cs2 = ClutchStore::new();
+    assert_eq!(read_store(&mut cm2, &mut cs2, &buf[..]).unwrap(), 3);
+    let mut buf2 = vec![];
+    write_store(&cm2, &cs2, &mut buf2).unwrap();
+    // same records, the extra group aside
+    let text = String::from_utf8(buf2).unwrap();
+    assert_eq!(text.replacen("group\tother\t\n", "", 1), String::from_utf8(buf).unwrap());
+
+    let g = cm2.get_group_by_name("cell\tstats").unwrap();
+    let key = ClutchKey::new(g.idx, "ne1\0cell\\1".to_string(), 1960, 900, -3600);
+    let cd = cs2.get(&key).unwrap();
+    assert_eq!(cd.suspect(), Some(SuspectReason::ClockChange));
+    assert_eq!(cd.get_f64(g, 6).unwrap(), Some(1.1));
+    assert!(matches!(cd.get_value(&g.om_map[&7]), OmValue::NULL));
+    assert_eq!(cd.get_str(g, 8).unwrap(), Some("sw\t1"));
+    assert!(read_store(&mut cm2, &mut cs2, "#clutch-export 1\nom\t1\tu32\t1\t0\n".as_bytes()).is_err());
+    assert!(text.contains("\t1969-12-31T23:32:40-01:00\t900\t-3600\t"));
+    let v1 = "#clutch-export 1\nclutch\tcell\\tstats\tne9\t1960\t900\t-3600\t-\n";
+    assert_eq!(read_store(&mut cm2, &mut cs2, v1.as_bytes()).unwrap(), 1);
+    assert!(cs2.get(&ClutchKey::new(key.groupidx, "ne9".to_string(), 1960, 900, -3600)).is_some());
+}
@@ -8,17 +8,33 @@ use structopt::StructOpt;
 
 use clutch::*;
 
-use crate::cli::Cli;
+use crate::cli::{Cli, Command};
 use std::rc::Rc;
 use crate::util::{StatTrack, PeriodicThread};
-use std::sync::atomic::Ordering;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use cpu_time::ProcessTime;
 
 mod clutch;
 mod util;
 mod cli;
+mod slab;
+mod columnar;
+mod retention;
+mod delta;
+mod rate;
+mod export;
+mod diff;
+mod topn;
+mod summary;
+mod metric;
+mod alarm;
+mod series;
+mod rollup;
+mod busyhour;
+mod completeness;
 
 use crate::util::{comma, rate};
+use crate::slab::SlabStore;
 
 #[cfg(target_family = "unix")]
 use jemallocator::Jemalloc;
@@ -54,6 +70,15 @@ fn main() {
 
 fn run_test() -> Result<(), Box<dyn std::error::Error>> {
     let cli = Arc::new(crate::cli::Cli::from_args());
+    if let Some(cmd) = &cli.cmd {
+        return run_command(cmd);
+    }
+    if cli.slab && cli.random_nulls > 0 {
+        return Err("-n (random nulls) needs explicit NULLs which --slab does not keep".into());
+    }
+    if cli.slab && cli.export.is_some() {
+        return Err("--export is not supported with --slab".into());
+    }
     let mut v = vec![];
     let total_cpu = ProcessTime::now();
     let start_d = Instant::now();
@@ -82,6 +107,99 @@ fn run_test() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+fn run_command(cmd: &Command) -> Result<(), Box<dyn std::error::Error>> {
+    match cmd {
+        Command::Diff { a, b, abs_tol, rel_tol } => {
+            // one meta for both so the stores line up group for group
+            let mut cm = ClutchMeta::new();
+            let mut sa = ClutchStore::new();
+            let mut sb = ClutchStore::new();
+            export::read_file(&mut cm, &mut sa, a)?;
+            export::read_file(&mut cm, &mut sb, b)?;
+            let d = diff::diff(&cm, &sa, &sb, &diff::DiffOpts { abs_tol: *abs_tol, rel_tol: *rel_tol });
+            d.write(&cm, std::io::stdout().lock())?;
+            if !d.is_empty() {
+                std::process::exit(1);
+            }
+        }
+        Command::Top { file, group, id, n, bottom, from, to } => {
+            let mut cm = ClutchMeta::new();
+            let mut cs = ClutchStore::new();
+            export::read_file(&mut cm, &mut cs, file)?;
+            let group = cm.get_group_by_name(group).ok_or_else(|| format!("no group {} in {}", group, file.display()))?.idx;
+            let rank = if *bottom { topn::Rank::Bottom } else { topn::Rank::Top };
+            let q = topn::TopNQuery { group, id: *id, from: *from, to: to.unwrap_or(u64::MAX), n: *n, rank };
+            for (i, (ck, v)) in topn::top_n(&cm, &cs, &q)?.iter().enumerate() {
+                println!("{:>4} {}  [{}] time: {} dur: {}", i + 1, v, ck.keys().replace('\0', ", "), ck.start_iso(), ck.dur());
+            }
+        }
+        Command::Stats { file, group, id, from, to, accuracy, histogram } => {
+            let mut cm = ClutchMeta::new();
+            let mut cs = ClutchStore::new();
+            export::read_file(&mut cm, &mut cs, file)?;
+            let group = cm.get_group_by_name(group).ok_or_else(|| format!("no group {} in {}", group, file.display()))?.idx;
+            let st = summary::om_stats(&cm, &cs, group, *id, *from, to.unwrap_or(u64::MAX), *accuracy)?;
+            println!("{}", st);
+            if *histogram {
+                for (lo, hi, cnt) in st.sketch.buckets() {
+                    println!("{:>14.6} .. {:<14.6} {}", lo, hi, cnt);
+                }
+            }
+        }
+        Command::Completeness { file, group, inventory, keys } => {
+            let mut cm = ClutchMeta::new();
+            let mut cs = ClutchStore::new();
+            export::read_file(&mut cm, &mut cs, file)?;
+            let group = cm.get_group_by_name(group).ok_or_else(|| format!("no group {} in {}", group, file.display()))?.idx;
+            let inventory = match inventory {
+                Some(path) => Some(completeness::read_inventory(path)?),
+                None => None,
+            };
+            for r in completeness::completeness(&cm, &cs, group, inventory.as_ref())? {
+                println!("{}", r);
+                if *keys {
+                    for k in r.missing.iter() {
+                        println!("    missing: {}", k.replace('\0', ", "));
+                    }
+                    for k in r.unexpected.iter() {
+                        println!("    unexpected: {}", k.replace('\0', ", "));
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn add_row_oms<D: OmSink>(data: &mut D, cli: &Cli, group: &mut OmGroup, pass: u32, k3: u32, om_stats: &AtomicUsize) -> u64 {
+    let mut om_count = 0u64;
+    for om_num in 1..=cli.oms {
+        // if cli.types & crate::cli::TU32 > 0 {
+        let idbase = pass*1000;
+        let id = idbase + om_num;
+        //let id = om_num + 1 + pass * 10000;
+        let tc = if cli.random_nulls > 0 && k3.is_multiple_of(cli.random_nulls) {
+            eval_result(data.set_null(false, group, id, &OmType::TypeU32))
+        } else {
+            eval_result(data.add_om_u32(false, group, id, id * 2))
+        };
+        om_stats.fetch_add(tc as usize, Ordering::Relaxed);
+        om_count += tc;
+        // }
+        // if cli.types & crate::cli::TF64 > 0 {
+        let id = om_num + idbase + 1000000; // om_num + 1 + pass * 10000 + 100000;
+        let tc = if cli.random_nulls > 0 && k3.is_multiple_of(cli.random_nulls) {
+            eval_result(data.set_null(false, group, id, &OmType::TypeF64))
+        } else {
+            eval_result(data.add_om_f64(false, group, id, (id * 2) as f64 + 0.25 as f64))
+        };
+        om_stats.fetch_add(tc as usize, Ordering::Relaxed);
+        om_count += tc;
+        // }
+    }
+    om_count
+}
+
 fn clutch_perf_test(n: u32, cli: Arc<Cli>) -> Result<(u64,u64), Box<dyn std::error::Error>> {
 
     let mut tot_oms = 0;
@@ -106,6 +224,7 @@ fn clutch_perf_test(n: u32, cli: Arc<Cli>) -> Result<(u64,u64), Box<dyn std::err
         let mut ticker = st.start(Duration::from_millis(cli.interval_ms));
 
         let mut cs = ClutchStore::new();
+        let mut ss = SlabStore::new();
 
         let mut om_count = 0u64;
         let mut row_count = 0u64;
@@ -133,33 +252,14 @@ fn clutch_perf_test(n: u32, cli: Arc<Cli>) -> Result<(u64,u64), Box<dyn std::err
                         // v.push(format!("{}", k2));
                         // v.push(format!("{}", k3));
 
-                        let data = cs.find_or_add_clutchdata(&group, &c_key);
-                        for om_num in 1..=cli.oms {
-                            // if cli.types & crate::cli::TU32 > 0 {
-                            let idbase = pass*1000;
-                            let id = idbase + om_num;
-                            //let id = om_num + 1 + pass * 10000;
-                            // if cli.random_nulls == 0 || k3 % cli.random_nulls == 1 {
-                            //     if cli.verbose > 1 {
-                            //         println!("u32 o: {} key: {} d: {}", pass, &c_key.to_string(), id);
-                            //     }
-                            let tc = eval_result(data.add_om_u32(false, group, id, id * 2));
-                            om_stats.fetch_add(tc as usize, Ordering::Relaxed);
-                            om_count += tc;
-                            // }
-                            // }
-                            // if cli.types & crate::cli::TF64 > 0 {
-                            let id = om_num + idbase + 1000000; // om_num + 1 + pass * 10000 + 100000;
-                            // if cli.random_nulls == 0 || k3 % cli.random_nulls == 0 {
-                            //     if cli.verbose > 1 {
-                            //         println!("f64 o: {} key: {} d: {}", pass, &c_key.to_string(), id);
-                            //     }
-                            let tc = eval_result(data.add_om_f64(false, group, id, (id * 2) as f64 + 0.25 as f64));
-                            om_stats.fetch_add(tc as usize, Ordering::Relaxed);
-                            om_count += tc;
-                            // }
-                            // }
-                        }
+                        let tc = if cli.slab {
+                            let mut data = ss.find_or_add_clutch(group, &c_key);
+                            add_row_oms(&mut data, &cli, group, pass, k3, &om_stats)
+                        } else {
+                            let data = cs.find_or_add_clutchdata(group, &c_key);
+                            add_row_oms(data, &cli, group, pass, k3, &om_stats)
+                        };
+                        om_count += tc;
                         row_stats.fetch_add(1, Ordering::Relaxed);
                         row_count += 1;
                     }
@@ -167,10 +267,27 @@ fn clutch_perf_test(n: u32, cli: Arc<Cli>) -> Result<(u64,u64), Box<dyn std::err
             }
         } // pass loop
         if cli.dump_level > 0 {
-            dump(&cm, &cs, !(cli.dump_level > 1));
+            if cli.slab {
+                slab::dump(&cm, &ss, cli.dump_level <= 1);
+            } else {
+                dump(&cm, &cs, !(cli.dump_level > 1));
+            }
+        }
+        if iteration == cli.iterations {
+            // walks every clutch, so only once the last iteration's data is in
+            let mu = if cli.slab { ss.memory_usage(&cm) } else { cs.memory_usage(&cm) };
+            println!("{} itr: {} mem {}", n, iteration, &mu.total);
+            for (idx, gm) in mu.groups.iter().enumerate().filter(|(_, gm)| gm.clutches > 0) {
+                println!("{}     group: {} {} late: {}", n, &cm.get_group(idx as u16).unwrap().group, gm,
+                         cs.late_arrivals(idx as u16));
+            }
+        }
+        if let (Some(path), 0, true) = (&cli.export, n, iteration == cli.iterations) {
+            export::write_file(&cm, &cs, path)?;
         }
         let clear_time = Instant::now();
         cs.clear_oms();
+        ss.clear_oms();
         cm.optimize();
         //println!("cleared in {}", clear_time.elapsed().as_secs_f64());
         ticker.map(|mut t| t.stop());
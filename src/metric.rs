@@ -0,0 +1,63 @@
+#![allow(dead_code)]
+
+use anyhow::{anyhow, Result};
+
+use crate::clutch::{ClutchData, OmGroup, OmType, OmValue};
+
+/// A number read from one clutch: an OM as is, or derived from OMs of the same clutch.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Metric {
+    Om(u32),
+    /// `num / den` as a fraction (0.95 is 95%), no value when `den` is 0
+    Ratio { num: u32, den: u32 },
+}
+
+fn om_f64(group: &OmGroup, cd: &ClutchData, id: u32) -> Option<f64> {
+    match cd.get_value(group.om_map.get(&id)?) {
+        OmValue::U32(v) => Some(v as f64),
+        OmValue::F64(v) if !v.is_nan() => Some(v),
+        _ => None,
+    }
+}
+
+impl Metric {
+    /// Checks every OM the metric reads is a number known to `group`.
+    pub fn check(&self, group: &OmGroup) -> Result<()> {
+        let ids = match self {
+            Metric::Om(id) => vec![*id],
+            Metric::Ratio { num, den } => vec![*num, *den],
+        };
+        for id in ids {
+            let meta = group.om_map.get(&id)
+                .ok_or_else(|| anyhow!("OM id: {} not known in group {}", id, &group.group))?;
+            if !matches!(meta.kind, OmType::TypeU32 | OmType::TypeF64) {
+                return Err(anyhow!("OM id: {} of type {} is not a number", id, &meta.kind));
+            }
+        }
+        Ok(())
+    }
+
+    /// The metric for one clutch, None when an OM it needs is NULL, not reported or NaN.
+    pub fn eval(&self, group: &OmGroup, cd: &ClutchData) -> Option<f64> {
+        match self {
+            Metric::Om(id) => om_f64(group, cd, *id),
+            Metric::Ratio { num, den } => {
+                let d = om_f64(group, cd, *den)?;
+                if d == 0.0 {
+                    None
+                } else {
+                    Some(om_f64(group, cd, *num)? / d)
+                }
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Metric {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Metric::Om(id) => write!(f, "{}", id),
+            Metric::Ratio { num, den } => write!(f, "{}/{}", num, den),
+        }
+    }
+}
@@ -0,0 +1,161 @@
+#![allow(dead_code)]
+
+use anyhow::{anyhow, Result};
+
+use crate::clutch::{ClutchKey, ClutchMeta, ClutchStore, OmType, SuspectReason};
+
+/// Where per-second rates are written.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RateTarget {
+    /// as f64 OMs in the same clutch, with id `id + id_offset`
+    InPlace { id_offset: u32 },
+    /// as f64 OMs with the same ids in a clutch of another group under the same key
+    Group(u16),
+}
+
+/// Rounding shared by everything that turns counters into rates, so two reports of the
+/// same data agree to the last digit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateOpts {
+    pub target: RateTarget,
+    /// decimals to round to (half away from zero), None keeps the full f64
+    pub decimals: Option<u32>,
+}
+
+/// target key, suspect flag and (id, rate, quality) for one clutch
+type ClutchRates = (ClutchKey, Option<SuspectReason>, Vec<(u32, f64, u8)>);
+
+/// `value / dur` rounded per `decimals`, None when the clutch has no duration.
+pub fn per_second(value: f64, dur: u32, decimals: Option<u32>) -> Option<f64> {
+    if dur == 0 {
+        return None;
+    }
+    let r = value / dur as f64;
+    Some(match decimals {
+        Some(d) => {
+            let m = 10f64.powi(d as i32);
+            (r * m).round() / m
+        }
+        None => r,
+    })
+}
+
+/// Computes per-second rates of `ids` for every clutch of group `src` and writes them as
+/// f64 OMs per `opts.target`.  Clutches with a zero `dur` or without the OM get no rate.
+/// Returns the number of rates written.
+pub fn rate_store(cm: &mut ClutchMeta, cs: &mut ClutchStore, src: u16, ids: &[u32], opts: &RateOpts) -> Result<usize> {
+    let sg = cm.get_group(src).ok_or_else(|| anyhow!("no group with index {}", src))?;
+    let mut metas = vec![];
+    for id in ids {
+        let meta = sg.om_map.get(id).ok_or_else(|| anyhow!("OM id: {} not known in group {}", id, &sg.group))?;
+        match meta.kind {
+            OmType::TypeU32 | OmType::TypeF64 => metas.push(meta),
+            _ => return Err(anyhow!("OM id: {} of type {} has no rate", id, &meta.kind)),
+        }
+    }
+    let dst = match opts.target {
+        RateTarget::InPlace { .. } => src,
+        RateTarget::Group(g) => g,
+    };
+    let dg = cm.get_group(dst).ok_or_else(|| anyhow!("no group with index {}", dst))?;
+    // (source OM, target id) - a target id must not wrap and, if known, must be f64
+    let mut targets = vec![];
+    for meta in metas {
+        let id = match opts.target {
+            RateTarget::InPlace { id_offset } => meta.id.checked_add(id_offset)
+                .ok_or_else(|| anyhow!("rate id of OM id: {} overflows with offset {}", meta.id, id_offset))?,
+            RateTarget::Group(_) => meta.id,
+        };
+        if let Some(m) = dg.om_map.get(&id).filter(|m| !matches!(m.kind, OmType::TypeF64)) {
+            return Err(anyhow!("rate OM id: {} is of type {} in group {}", id, &m.kind, &dg.group));
+        }
+        targets.push((meta, id));
+    }
+
+    // the source clutch's suspect flag and OM quality bits go with the rates
+    let mut rates: Vec<ClutchRates> = vec![];
+    for (ck, cd) in cs.iter().filter(|(ck, _)| ck.groupidx == src) {
+        let mut v = vec![];
+        for (meta, id) in targets.iter() {
+            let val = match meta.kind {
+                OmType::TypeU32 => cd.get_32(meta.slot).map(|v| v as f64),
+                _ => cd.get_64(meta.slot).map(f64::from_bits),
+            };
+            if let Some(r) = val.and_then(|val| per_second(val, ck.dur(), opts.decimals)) {
+                v.push((*id, r, cd.quality(meta.id)));
+            }
+        }
+        if !v.is_empty() {
+            let mut key = ck.clone();
+            key.groupidx = dst;
+            rates.push((key, cd.suspect(), v));
+        }
+    }
+
+    let group = cm.get_group_by_idx(dst).unwrap();
+    let mut cnt = 0;
+    for (key, suspect, v) in rates {
+        let cd = cs.find_or_add_clutchdata(group, &key);
+        if let (Some(reason), None) = (suspect, cd.suspect()) {
+            cd.set_suspect(if dst == src { reason } else { reason.propagate() });
+        }
+        for (id, r, q) in v {
+            cd.add_om_f64(true, group, id, r)?;
+            cd.add_quality(id, q);
+            cnt += 1;
+        }
+    }
+    Ok(cnt)
+}
+
+#[test]
+fn test_rates() {
+    use crate::clutch::OmValue;
+
+    assert_eq!(per_second(1000.0, 900, None), Some(1000.0 / 900.0));
+    assert_eq!(per_second(1000.0, 900, Some(2)), Some(1.11));
+    assert_eq!(per_second(5.0, 0, Some(2)), None);
+
+    let mut cm = ClutchMeta::new();
+    let mut cs = ClutchStore::new();
+    let group = cm.find_or_new_group("ctr");
+    let src = group.idx;
+    for (k, dur) in [(1u32, 900u32), (2, 60), (3, 0)].iter() {
+        let key = ClutchKey::new(group.idx, k.to_string(), 0, *dur, 0);
+        let cd = cs.find_or_add_clutchdata(group, &key);
+        cd.add_om_u32(false, group, 1, 1800 * k).unwrap();
+        cd.add_om_f64(false, group, 2, 90.0).unwrap();
+        if *k == 1 {
+            cd.set_suspect(SuspectReason::Incomplete);
+            cd.set_quality(1, crate::clutch::QUALITY_ESTIMATED);
+        }
+    }
+    let rates = cm.find_or_new_group("ctr_rates").idx;
+
+    let opts = RateOpts { target: RateTarget::InPlace { id_offset: 100_000 }, decimals: Some(3) };
+    assert_eq!(rate_store(&mut cm, &mut cs, src, &[1, 2], &opts).unwrap(), 4);
+    let opts = RateOpts { target: RateTarget::Group(rates), decimals: None };
+    assert_eq!(rate_store(&mut cm, &mut cs, src, &[1], &opts).unwrap(), 2);
+    assert!(rate_store(&mut cm, &mut cs, src, &[77], &opts).is_err());
+    let opts = RateOpts { target: RateTarget::InPlace { id_offset: u32::MAX }, decimals: None };
+    assert!(rate_store(&mut cm, &mut cs, src, &[1], &opts).is_err());
+    // the rate of OM 1 would land on the u32 OM 1 itself
+    let opts = RateOpts { target: RateTarget::Group(src), decimals: None };
+    assert!(rate_store(&mut cm, &mut cs, src, &[1], &opts).is_err());
+
+    let g = cm.get_group(src).unwrap();
+    let key = ClutchKey::new(src, "2".to_string(), 0, 60, 0);
+    let cd = cs.get(&key).unwrap();
+    assert!(matches!(cd.get_value(g.om_map.get(&100_001).unwrap()), OmValue::F64(v) if v == 60.0));
+    assert!(matches!(cd.get_value(g.om_map.get(&100_002).unwrap()), OmValue::F64(v) if v == 1.5));
+    let key = ClutchKey::new(src, "3".to_string(), 0, 0, 0);
+    assert!(matches!(cs.get(&key).unwrap().get_value(g.om_map.get(&100_001).unwrap()), OmValue::Unset));
+
+    let g = cm.get_group(rates).unwrap();
+    let key = ClutchKey::new(rates, "1".to_string(), 0, 900, 0);
+    let cd = cs.get(&key).unwrap();
+    assert!(matches!(cd.get_value(g.om_map.get(&1).unwrap()), OmValue::F64(v) if v == 2.0));
+    assert_eq!(cd.suspect(), Some(SuspectReason::Propagated));
+    assert_eq!(cd.quality(1), crate::clutch::QUALITY_ESTIMATED);
+    assert_eq!(cs.len(), 5);
+}
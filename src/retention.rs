@@ -0,0 +1,86 @@
+#![allow(dead_code)]
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::clutch::{ClutchData, ClutchKey, ClutchMeta, ClutchStore};
+use crate::util::PeriodicThread;
+
+pub fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// Runs `ClutchStore::expire` against the wall clock every `every`, handing anything
+/// removed to `on_expired`.  Stop the returned thread to end it.
+pub fn start_expiry<F>(cm: Arc<Mutex<ClutchMeta>>, cs: Arc<Mutex<ClutchStore>>, every: Duration, mut on_expired: F) -> PeriodicThread
+    where F: 'static + Send + FnMut(Vec<(ClutchKey, ClutchData)>)
+{
+    let mut t = PeriodicThread::new(every);
+    t.start(move |stopping| {
+        if stopping {
+            return;
+        }
+        // meta first then store - the same order writers have to use
+        let gone = {
+            let cm = cm.lock().unwrap();
+            cs.lock().unwrap().expire(&cm, now_secs())
+        };
+        if !gone.is_empty() {
+            on_expired(gone);
+        }
+    });
+    t
+}
+
+#[test]
+fn test_retention() {
+    const HOUR: u64 = 3600;
+    const DAY: u64 = 24 * HOUR;
+
+    let mut cm = ClutchMeta::new();
+    let mut cs = ClutchStore::new();
+    let raw = cm.find_or_new_group("raw");
+    raw.retention = Some(Duration::from_secs(7 * DAY));
+    for t in (0..10 * DAY).step_by(DAY as usize) {
+        let key = ClutchKey::new(raw.idx, "cell1".to_string(), t, 900, 0);
+        cs.find_or_add_clutchdata(raw, &key).add_om_u32(false, raw, 1, 1).unwrap();
+    }
+    let hourly = cm.find_or_new_group("hourly");
+    hourly.retention = Some(Duration::from_secs(90 * DAY));
+    for t in (0..10 * DAY).step_by(DAY as usize) {
+        let key = ClutchKey::new(hourly.idx, "cell1".to_string(), t, HOUR as u32, 0);
+        cs.find_or_add_clutchdata(hourly, &key).add_om_u32(false, hourly, 1, 1).unwrap();
+    }
+    let forever = cm.find_or_new_group("forever");
+    let key = ClutchKey::new(forever.idx, "cell1".to_string(), 0, 900, 0);
+    cs.find_or_add_clutchdata(forever, &key);
+
+    // raw periods ending at or before day 10 + 15 min are gone: days 0..=3
+    let gone = cs.expire(&cm, 10 * DAY + 900);
+    assert_eq!(gone.len(), 4);
+    assert!(gone.iter().all(|(k, _)| k.groupidx == 1 && k.time() <= 3 * DAY));
+    assert_eq!(cs.len(), 6 + 10 + 1);
+    assert!(cs.expire(&cm, 10 * DAY + 900).is_empty());
+
+    let gone = cs.expire(&cm, 100 * DAY);
+    assert_eq!(gone.len(), 6 + 10);
+    assert_eq!(cs.len(), 1);
+    assert_eq!(cs.periods().collect::<Vec<_>>(), vec![(0, 1)]);
+
+    // the background version against the wall clock
+    let cm = Arc::new(Mutex::new(cm));
+    let cs = Arc::new(Mutex::new(cs));
+    {
+        let mut cm = cm.lock().unwrap();
+        let raw = cm.get_group_by_name("raw").unwrap();
+        let key = ClutchKey::new(raw.idx, "cell2".to_string(), 0, 900, 0);
+        cs.lock().unwrap().find_or_add_clutchdata(raw, &key);
+    }
+    let expired = Arc::new(Mutex::new(0));
+    let cnt = expired.clone();
+    let mut t = start_expiry(cm, cs.clone(), Duration::from_millis(20), move |gone| *cnt.lock().unwrap() += gone.len());
+    std::thread::sleep(Duration::from_millis(200));
+    t.stop();
+    assert_eq!(*expired.lock().unwrap(), 1);
+    assert_eq!(cs.lock().unwrap().len(), 1);
+}
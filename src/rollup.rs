@@ -0,0 +1,460 @@
+#![allow(dead_code)]
+
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use chrono::{Datelike, Duration, NaiveDate};
+use fnv::FnvHashMap;
+
+use crate::clutch::{ClutchKey, ClutchMeta, ClutchStore, OmType, SuspectReason, QUALITY_CAPPED};
+
+/*
+Hourly, daily and weekly rollups in local time.
+
+Time zones are POSIX TZ rules, the same strings libc takes in $TZ:
+
+    CET-1CEST,M3.5.0,M10.5.0/3      EST5EDT,M3.2.0,M11.1.0      UTC0
+
+Offsets there are hours west of UTC, so CET-1 is an hour east.  A rule gives the offset
+at any instant, which is all a rollup needs: a local day runs from one local midnight to
+the next, so in UTC it is 23 hours long the day clocks go forward and 25 the day they go
+back.  Midnight that falls in a forward gap starts the day at the gap's end, midnight
+that happens twice starts it at the first.  Weeks start on Monday.
+
+A source clutch goes to the day its start falls in, whatever its length.  Output clutches
+get the local start, the real length in seconds and the offset in force at the start.
+*/
+
+/// A daylight saving change, within some year.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Change {
+    /// day `d` (0 Sunday) of week `w` (5 the last) of month `m`
+    Month { m: u32, w: u32, d: u32 },
+    /// day 1-365 not counting Feb 29
+    Julian(u32),
+    /// day 0-365 counting Feb 29
+    Day(u32),
+}
+
+/// The transition and the local time of day, in seconds, it happens at.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Transition {
+    change: Change,
+    at: i32,
+}
+
+/// A POSIX TZ rule, offsets in seconds east of UTC.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TzRule {
+    std: i32,
+    /// the DST offset and when it starts and ends
+    dst: Option<(i32, Transition, Transition)>,
+}
+
+const DAY: i64 = 86400;
+
+fn epoch_day(d: NaiveDate) -> i64 {
+    (d - NaiveDate::from_ymd_opt(1970, 1, 1).unwrap()).num_days()
+}
+
+fn date_of(day: i64) -> NaiveDate {
+    NaiveDate::from_ymd_opt(1970, 1, 1).unwrap() + Duration::days(day)
+}
+
+impl Transition {
+    /// the local date it falls on in year `y`
+    fn date(&self, y: i32) -> Option<NaiveDate> {
+        let jan1 = NaiveDate::from_ymd_opt(y, 1, 1)?;
+        match self.change {
+            Change::Month { m, w, d } => {
+                let first = NaiveDate::from_ymd_opt(y, m, 1)?;
+                let mut day = 1 + (d + 7 - first.weekday().num_days_from_sunday()) % 7 + (w - 1) * 7;
+                while NaiveDate::from_ymd_opt(y, m, day).is_none() {
+                    day -= 7;
+                }
+                NaiveDate::from_ymd_opt(y, m, day)
+            }
+            Change::Julian(n) => {
+                let leap = NaiveDate::from_ymd_opt(y, 2, 29).is_some();
+                Some(jan1 + Duration::days(n as i64 - 1 + if leap && n >= 60 { 1 } else { 0 }))
+            }
+            Change::Day(n) => Some(jan1 + Duration::days(n as i64)),
+        }
+    }
+
+    /// the UTC instant it happens in year `y`, its time of day being local at `offset`
+    fn utc(&self, y: i32, offset: i32) -> Option<i64> {
+        Some(epoch_day(self.date(y)?) * DAY + (self.at - offset) as i64)
+    }
+}
+
+/// [+-]hh[:mm[:ss]] as seconds, from the front of `s`
+fn parse_hms(s: &mut &str) -> Result<i32> {
+    let neg = s.starts_with('-');
+    if neg || s.starts_with('+') {
+        *s = &s[1..];
+    }
+    let mut secs = 0;
+    for (i, scale) in [3600, 60, 1].iter().enumerate() {
+        if i > 0 {
+            if !s.starts_with(':') {
+                break;
+            }
+            *s = &s[1..];
+        }
+        let n = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        if n == 0 {
+            return Err(anyhow!("expected a number at {}", s));
+        }
+        secs += s[..n].parse::<i32>()? * scale;
+        *s = &s[n..];
+    }
+    Ok(if neg { -secs } else { secs })
+}
+
+/// a zone name, plain letters or in <>, from the front of `s`
+fn parse_name<'a>(s: &mut &'a str) -> Result<&'a str> {
+    let (name, rest) = if let Some(quoted) = s.strip_prefix('<') {
+        let end = quoted.find('>').ok_or_else(|| anyhow!("unterminated <name>"))?;
+        (&quoted[..end], &quoted[end + 1..])
+    } else {
+        let end = s.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(s.len());
+        (&s[..end], &s[end..])
+    };
+    if name.len() < 3 {
+        return Err(anyhow!("zone name {} is under 3 letters", name));
+    }
+    *s = rest;
+    Ok(name)
+}
+
+fn parse_transition(s: &str) -> Result<Transition> {
+    let (date, time) = match s.split_once('/') {
+        Some((d, t)) => (d, Some(t)),
+        None => (s, None),
+    };
+    let change = if let Some(m) = date.strip_prefix('M') {
+        let parts: Vec<u32> = m.split('.').map(|p| p.parse()).collect::<Result<_, _>>()?;
+        match parts[..] {
+            [m, w, d] if (1..=12).contains(&m) && (1..=5).contains(&w) && d <= 6 => Change::Month { m, w, d },
+            _ => return Err(anyhow!("bad Mm.w.d change {}", date)),
+        }
+    } else if let Some(n) = date.strip_prefix('J') {
+        match n.parse()? {
+            n @ 1..=365 => Change::Julian(n),
+            n => return Err(anyhow!("Julian day {} is not in 1-365", n)),
+        }
+    } else {
+        match date.parse()? {
+            n @ 0..=365 => Change::Day(n),
+            n => return Err(anyhow!("day {} is not in 0-365", n)),
+        }
+    };
+    let at = match time {
+        Some(mut t) => {
+            let at = parse_hms(&mut t)?;
+            if !t.is_empty() {
+                return Err(anyhow!("trailing {} after change time", t));
+            }
+            at
+        }
+        None => 2 * 3600,
+    };
+    Ok(Transition { change, at })
+}
+
+impl FromStr for TzRule {
+    type Err = anyhow::Error;
+
+    fn from_str(tz: &str) -> Result<TzRule> {
+        let mut s = tz;
+        let name = parse_name(&mut s).map_err(|e| anyhow!("TZ {}: {}", tz, e))?;
+        (|| {
+            if s.is_empty() && (name == "UTC" || name == "GMT") {
+                return Ok(TzRule { std: 0, dst: None });
+            }
+            let std = -parse_hms(&mut s)?;
+            if s.is_empty() {
+                return Ok(TzRule { std, dst: None });
+            }
+            parse_name(&mut s)?;
+            let dst = if s.starts_with(',') { std + 3600 } else { -parse_hms(&mut s)? };
+            let rules = s.strip_prefix(',').ok_or_else(|| anyhow!("DST without its start and end"))?;
+            let (start, end) = rules.split_once(',').ok_or_else(|| anyhow!("DST without an end"))?;
+            Ok(TzRule { std, dst: Some((dst, parse_transition(start)?, parse_transition(end)?)) })
+        })().map_err(|e: anyhow::Error| anyhow!("TZ {}: {}", tz, e))
+    }
+}
+
+impl TzRule {
+    /// a zone without DST
+    pub fn fixed(offset: i32) -> TzRule {
+        TzRule { std: offset, dst: None }
+    }
+
+    /// Offset in seconds east of UTC at UTC instant `t`.
+    pub fn offset_at(&self, t: i64) -> i32 {
+        let (dst, start, end) = match &self.dst {
+            Some(d) => d,
+            None => return self.std,
+        };
+        let y = date_of((t + self.std as i64).div_euclid(DAY)).year();
+        // the start is in standard time and the end in DST
+        let (s, e) = match (start.utc(y, self.std), end.utc(y, *dst)) {
+            (Some(s), Some(e)) => (s, e),
+            _ => return self.std,
+        };
+        let in_dst = if s < e { t >= s && t < e } else { t >= s || t < e };
+        if in_dst { *dst } else { self.std }
+    }
+
+    /// UTC instant of local midnight starting `date`.
+    pub fn midnight(&self, date: NaiveDate) -> i64 {
+        let local = epoch_day(date) * DAY;
+        let offsets = match &self.dst {
+            Some((dst, _, _)) => vec![self.std.max(*dst), self.std.min(*dst)],
+            None => vec![self.std],
+        };
+        offsets.iter().map(|o| local - *o as i64)
+            .find(|t| local == t + self.offset_at(*t) as i64)
+            // in a forward gap, the first instant after it
+            .unwrap_or(local - self.std as i64)
+    }
+
+    /// the local date at UTC instant `t`
+    pub fn date(&self, t: i64) -> NaiveDate {
+        date_of((t + self.offset_at(t) as i64).div_euclid(DAY))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Span {
+    /// local clock hours, which are UTC hours unless the offset has minutes
+    Hour,
+    Day,
+    /// Monday to Monday
+    Week,
+}
+
+/// How an OM's values within a rollup period combine.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Agg {
+    Sum,
+    Min,
+    Max,
+    /// always written as f64
+    Avg,
+}
+
+#[derive(Debug, Clone)]
+pub struct RollupOpts {
+    pub tz: TzRule,
+    pub span: Span,
+    /// group of the rolled up clutches, can be the source group as they go to a new store
+    pub target: u16,
+    /// OMs not listed are summed
+    pub aggs: FnvHashMap<u32, Agg>,
+}
+
+#[derive(Debug, Default)]
+struct OmAcc {
+    count: u32,
+    sum: f64,
+    min: f64,
+    max: f64,
+    nulls: u32,
+    quality: u8,
+}
+
+#[derive(Debug, Default)]
+struct PeriodAcc {
+    /// seconds of source clutches seen
+    covered: u64,
+    suspect: bool,
+    oms: BTreeMap<u32, OmAcc>,
+}
+
+impl RollupOpts {
+    /// (UTC start, UTC end) of the local period containing UTC instant `t`
+    pub fn period(&self, t: i64) -> (i64, i64) {
+        let date = self.tz.date(t);
+        let (first, days) = match self.span {
+            Span::Hour => {
+                let off = self.tz.offset_at(t) as i64;
+                let start = (t + off).div_euclid(3600) * 3600 - off;
+                return (start, start + 3600);
+            }
+            Span::Day => (date, 1),
+            Span::Week => (date - Duration::days(date.weekday().num_days_from_monday() as i64), 7),
+        };
+        (self.tz.midnight(first), self.tz.midnight(first + Duration::days(days)))
+    }
+
+    fn agg(&self, id: u32) -> Agg {
+        self.aggs.get(&id).copied().unwrap_or(Agg::Sum)
+    }
+}
+
+/// Rolls up the u32 and f64 OMs of every clutch of group `src` into a new store with a
+/// clutch per key and local hour, day or week.  NULLs are left out of the values but an OM that
+/// was only ever NULL stays NULL.  Quality bits are OR-ed, any suspect source clutch makes
+/// the rollup's suspect and one not fully covered by source clutches is `Incomplete`.
+pub fn rollup_store(cm: &mut ClutchMeta, cs: &ClutchStore, src: u16, opts: &RollupOpts) -> Result<ClutchStore> {
+    let sg = cm.get_group(src).ok_or_else(|| anyhow!("no group with index {}", src))?;
+    let mut metas: Vec<_> = sg.om_map.values()
+        .filter(|m| matches!(m.kind, OmType::TypeU32 | OmType::TypeF64))
+        .cloned()
+        .collect();
+    metas.sort_unstable_by_key(|m| m.id);
+    let tg = cm.get_group(opts.target).ok_or_else(|| anyhow!("no group with index {}", opts.target))?;
+    for m in metas.iter() {
+        let kind = if opts.agg(m.id) == Agg::Avg { OmType::TypeF64 } else { m.kind.clone() };
+        if let Some(t) = tg.om_map.get(&m.id) {
+            if std::mem::discriminant(&t.kind) != std::mem::discriminant(&kind) {
+                return Err(anyhow!("OM id: {} rolls up to {} but is {} in group {}", m.id, kind, &t.kind, &tg.group));
+            }
+        }
+    }
+
+    // by keys and UTC start, with the UTC end
+    let mut periods: BTreeMap<(&str, i64), (i64, PeriodAcc)> = BTreeMap::new();
+    for (ck, cd) in cs.iter().filter(|(ck, _)| ck.groupidx == src) {
+        let (start, end) = opts.period(ck.time() as i64);
+        let (_, acc) = periods.entry((ck.keys(), start)).or_insert_with(|| (end, PeriodAcc::default()));
+        acc.covered += ck.dur() as u64;
+        acc.suspect |= cd.suspect().is_some();
+        for m in metas.iter() {
+            let val = match m.kind {
+                OmType::TypeU32 => cd.get_32(m.slot).map(|v| v as f64),
+                _ => cd.get_64(m.slot).map(f64::from_bits),
+            };
+            let null = match m.kind {
+                OmType::TypeU32 => cd.is_32_null(m.slot),
+                _ => cd.is_64_null(m.slot),
+            };
+            if val.is_none() && !null {
+                continue;
+            }
+            let om = acc.oms.entry(m.id).or_default();
+            om.quality |= cd.quality(m.id);
+            match val {
+                Some(v) => {
+                    om.min = if om.count == 0 { v } else { om.min.min(v) };
+                    om.max = if om.count == 0 { v } else { om.max.max(v) };
+                    om.sum += v;
+                    om.count += 1;
+                }
+                None => om.nulls += 1,
+            }
+        }
+    }
+
+    let group = cm.get_group_by_idx(opts.target).unwrap();
+    let mut out = ClutchStore::new();
+    for ((keys, start), (end, acc)) in periods {
+        let key = ClutchKey::new(opts.target, keys.to_string(), start as u64, (end - start) as u32, opts.tz.offset_at(start));
+        let cd = out.find_or_add_clutchdata(group, &key);
+        if acc.suspect {
+            cd.set_suspect(SuspectReason::Propagated);
+        } else if acc.covered < key.dur() as u64 {
+            cd.set_suspect(SuspectReason::Incomplete);
+        }
+        for m in metas.iter() {
+            let om = match acc.oms.get(&m.id) {
+                Some(om) => om,
+                None => continue,
+            };
+            let agg = opts.agg(m.id);
+            if om.count == 0 {
+                let kind = if agg == Agg::Avg { OmType::TypeF64 } else { m.kind.clone() };
+                cd.set_null(true, group, m.id, &kind)?;
+            } else {
+                let v = match agg {
+                    Agg::Sum => om.sum,
+                    Agg::Min => om.min,
+                    Agg::Max => om.max,
+                    Agg::Avg => om.sum / om.count as f64,
+                };
+                match (agg, &m.kind) {
+                    (Agg::Avg, _) | (_, OmType::TypeF64) => cd.add_om_f64(true, group, m.id, v)?,
+                    _ if v > u32::MAX as f64 => {
+                        cd.add_om_u32(true, group, m.id, u32::MAX)?;
+                        cd.add_quality(m.id, QUALITY_CAPPED);
+                    }
+                    _ => cd.add_om_u32(true, group, m.id, v as u32)?,
+                }
+            }
+            cd.add_quality(m.id, om.quality);
+        }
+    }
+    Ok(out)
+}
+
+#[test]
+fn test_rollup_dst() {
+    use crate::clutch::OmValue;
+
+    let cet: TzRule = "CET-1CEST,M3.5.0,M10.5.0/3".parse().unwrap();
+    let day = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+    // 2020: forward Mar 29 01:00 UTC, back Oct 25 01:00 UTC
+    assert_eq!(cet.offset_at(1585443599), 3600);
+    assert_eq!(cet.offset_at(1585443600), 7200);
+    assert_eq!(cet.offset_at(1603587600), 3600);
+    assert_eq!(cet.midnight(day(2020, 3, 30)) - cet.midnight(day(2020, 3, 29)), 23 * 3600);
+    assert_eq!(cet.midnight(day(2020, 10, 26)) - cet.midnight(day(2020, 10, 25)), 25 * 3600);
+    // southern hemisphere, DST over new year
+    let aest: TzRule = "AEST-10AEDT,M10.1.0,M4.1.0/3".parse().unwrap();
+    assert_eq!((aest.offset_at(1577836800), aest.offset_at(1593561600)), (39600, 36000));
+    // forward at midnight, the day starts at 01:00
+    let gap: TzRule = "<-03>3<-02>,M3.5.0/0,M10.5.0/0".parse().unwrap();
+    assert_eq!(gap.midnight(day(2020, 3, 29)), epoch_day(day(2020, 3, 29)) * DAY + 3 * 3600);
+    assert!("CET-1CEST".parse::<TzRule>().is_err());
+    assert!("CET-1CEST,M13.5.0,M10.5.0".parse::<TzRule>().is_err());
+    assert_eq!("UTC".parse::<TzRule>().unwrap(), TzRule::fixed(0));
+
+    let mut cm = ClutchMeta::new();
+    let mut cs = ClutchStore::new();
+    let g = cm.find_or_new_group("hourly");
+    // every hour from local midnight Mar 28 to local midnight Mar 31, one missing on the 30th
+    let from = cet.midnight(day(2020, 3, 28));
+    let to = cet.midnight(day(2020, 3, 31));
+    for t in (from..to).step_by(3600) {
+        if t == to - 3600 {
+            continue;
+        }
+        let key = ClutchKey::new(g.idx, "cell1".to_string(), t as u64, 3600, cet.offset_at(t));
+        let cd = cs.find_or_add_clutchdata(g, &key);
+        cd.add_om_u32(false, g, 1, 1).unwrap();
+        cd.add_om_f64(false, g, 2, (t - from) as f64 / 3600.0).unwrap();
+        if t == from {
+            cd.set_suspect(SuspectReason::ClockChange);
+        }
+    }
+    let gi = g.idx;
+    let mut aggs = FnvHashMap::default();
+    aggs.insert(2, Agg::Max);
+    let mut opts = RollupOpts { tz: cet.clone(), span: Span::Day, target: gi, aggs };
+    let days = rollup_store(&mut cm, &cs, gi, &opts).unwrap();
+    let g = cm.get_group_by_name("hourly").unwrap();
+    let got = days.iter().map(|(k, cd)| format!("{} {} {} {:?} {}", k.start_iso(), k.dur(), cd.get_value(&g.om_map[&1]),
+                                                  cd.suspect(), cd.get_value(&g.om_map[&2])))
+        .collect::<Vec<_>>();
+    assert_eq!(got, vec![
+        "2020-03-28T00:00:00+01:00 86400 24 Some(Propagated) 23",
+        "2020-03-29T00:00:00+01:00 82800 23 None 46",
+        "2020-03-30T00:00:00+02:00 86400 23 Some(Incomplete) 69",
+    ]);
+
+    opts.span = Span::Week;
+    opts.aggs.insert(1, Agg::Avg);
+    assert!(rollup_store(&mut cm, &cs, gi, &opts).is_err());
+    let wk = cm.find_or_new_group("weekly").idx;
+    opts.target = wk;
+    let weeks = rollup_store(&mut cm, &cs, gi, &opts).unwrap();
+    let g = cm.get_group_by_name("weekly").unwrap();
+    let w: Vec<_> = weeks.iter().collect();
+    assert_eq!(w.len(), 2);
+    assert_eq!((w[0].0.start_iso().as_str(), w[0].0.dur()), ("2020-03-23T00:00:00+01:00", 7 * 86400 - 3600));
+    assert!(matches!(w[1].1.get_value(&g.om_map[&1]), OmValue::F64(v) if v == 1.0));
+}
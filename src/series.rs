@@ -0,0 +1,173 @@
+#![allow(dead_code)]
+
+use std::collections::btree_map::Range;
+use std::iter::Peekable;
+
+use anyhow::{anyhow, Result};
+
+use crate::clutch::{ClutchData, ClutchKey, ClutchMeta, ClutchStore, OmMeta, OmType, OmValue};
+
+/*
+Time series of one OM for one key.
+
+The clutches come from a range of the store, so the cost is the clutches of that key and
+not the whole store.  Gaps are periods missing between two clutches: after a clutch at t
+with duration d the next one is expected at t + d, and with a fill set every missing
+step until the next clutch is produced at the duration of the one before it.  Nothing
+is filled before the first clutch or after the last, there is nothing to step from or
+towards.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fill {
+    /// gaps are left out
+    None,
+    /// a NULL for every missing period
+    Null,
+    /// the last value repeated
+    Previous,
+    /// a straight line between the values either side, NULL when either is not a number
+    Linear,
+}
+
+#[derive(Debug, Clone)]
+pub struct SeriesQuery {
+    pub group: u16,
+    /// the full key, parts \0 separated
+    pub keys: String,
+    pub id: u32,
+    pub from: u64,
+    pub to: u64,
+    pub fill: Fill,
+}
+
+/// Yields `(time, dur, value)` in time order.  A clutch without the OM yields `Unset`,
+/// filled periods are never `Unset`.
+pub struct Series<'a> {
+    meta: &'a OmMeta,
+    fill: Fill,
+    clutches: Peekable<Range<'a, ClutchKey, ClutchData>>,
+    /// time, dur and value of the last clutch yielded
+    last: Option<(u64, u32, OmValue)>,
+    /// where the next filled period would start
+    gap_at: u64,
+}
+
+fn as_f64(v: &OmValue) -> Option<f64> {
+    match v {
+        OmValue::U32(v) => Some(*v as f64),
+        OmValue::F64(v) => Some(*v),
+        _ => None,
+    }
+}
+
+impl<'a> Series<'a> {
+    fn filled(&self, t: u64, next: (&ClutchKey, &ClutchData)) -> OmValue {
+        let (lt, _, lv) = self.last.as_ref().unwrap();
+        match (self.fill, lv) {
+            (Fill::Previous, OmValue::Unset) => OmValue::NULL,
+            (Fill::Previous, v) => v.clone(),
+            (Fill::Linear, v) => {
+                let nv = next.1.get_value(self.meta);
+                match (as_f64(v), as_f64(&nv)) {
+                    (Some(a), Some(b)) => {
+                        let y = a + (b - a) * (t - lt) as f64 / (next.0.time() - lt) as f64;
+                        match v {
+                            OmValue::U32(_) => OmValue::U32(y.round() as u32),
+                            _ => OmValue::F64(y),
+                        }
+                    }
+                    _ => OmValue::NULL,
+                }
+            }
+            _ => OmValue::NULL,
+        }
+    }
+}
+
+impl<'a> Iterator for Series<'a> {
+    type Item = (u64, u32, OmValue);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let (Some((_, dur, _)), Some(next)) = (self.last.as_ref(), self.clutches.peek().copied()) {
+            let dur = *dur;
+            if self.fill != Fill::None && dur > 0 && self.gap_at < next.0.time() {
+                let t = self.gap_at;
+                self.gap_at += dur as u64;
+                return Some((t, dur, self.filled(t, next)));
+            }
+        }
+        let (ck, cd) = self.clutches.next()?;
+        let v = cd.get_value(self.meta);
+        self.last = Some((ck.time(), ck.dur(), v.clone()));
+        self.gap_at = ck.time() + ck.dur() as u64;
+        Some((ck.time(), ck.dur(), v))
+    }
+}
+
+/// The series `q` asks for, checked against the group.
+pub fn series<'a>(cm: &'a ClutchMeta, cs: &'a ClutchStore, q: &SeriesQuery) -> Result<Series<'a>> {
+    let group = cm.get_group(q.group).ok_or_else(|| anyhow!("no group with index {}", q.group))?;
+    let meta = group.om_map.get(&q.id)
+        .ok_or_else(|| anyhow!("OM id: {} not known in group {}", q.id, &group.group))?;
+    if !matches!(meta.kind, OmType::TypeU32 | OmType::TypeF64) {
+        return Err(anyhow!("OM id: {} of type {} has no series", q.id, &meta.kind));
+    }
+    Ok(Series {
+        meta,
+        fill: q.fill,
+        clutches: cs.key_range(q.group, &q.keys, q.from, q.to).peekable(),
+        last: None,
+        gap_at: 0,
+    })
+}
+
+#[test]
+fn test_series() {
+    let mut cm = ClutchMeta::new();
+    let mut cs = ClutchStore::new();
+    let g = cm.find_or_new_group("cells");
+    // cell1 has 0, 900, 3600 and 4500 (with OM 1 unreported), 2700 NULL
+    for (keys, t) in [("cell1", 0u64), ("cell1", 900), ("cell1", 2700), ("cell1", 3600), ("cell1", 4500),
+                      ("cell0", 1800), ("cell2", 1800), ("cell1\0x", 1800)] {
+        let key = ClutchKey::new(g.idx, keys.to_string(), t, 900, 0);
+        let cd = cs.find_or_add_clutchdata(g, &key);
+        match t {
+            2700 => cd.set_null(false, g, 1, &OmType::TypeU32).unwrap(),
+            4500 => cd.add_om_u32(false, g, 2, 0).unwrap(),
+            _ => cd.add_om_u32(false, g, 1, t as u32 / 9).unwrap(),
+        }
+    }
+    // three missing periods between 0 and 3600
+    for (t, v) in [(0u64, 0), (3600, 40)] {
+        let key = ClutchKey::new(g.idx, "cell9".to_string(), t, 900, 0);
+        let cd = cs.find_or_add_clutchdata(g, &key);
+        cd.add_om_u32(false, g, 1, v).unwrap();
+        cd.add_om_str(false, g, 9, "sw1").unwrap();
+    }
+    let gi = g.idx;
+    let mut q = SeriesQuery { group: gi, keys: "cell1".to_string(), id: 1, from: 0, to: u64::MAX, fill: Fill::None };
+    let run = |q: &SeriesQuery| series(&cm, &cs, q).unwrap()
+        .map(|(t, d, v)| format!("{}+{}={}", t, d, v)).collect::<Vec<_>>().join(" ");
+    assert_eq!(run(&q), "0+900=0 900+900=100 2700+900=NULL 3600+900=400 4500+900=UNSET");
+    q.fill = Fill::Previous;
+    assert_eq!(run(&q), "0+900=0 900+900=100 1800+900=100 2700+900=NULL 3600+900=400 4500+900=UNSET");
+    q.fill = Fill::Null;
+    q.from = 900;
+    q.to = 3600;
+    assert_eq!(run(&q), "900+900=100 1800+900=NULL 2700+900=NULL");
+
+    q.keys = "cell9".to_string();
+    q.from = 0;
+    q.to = u64::MAX;
+    q.fill = Fill::Linear;
+    assert_eq!(run(&q), "0+900=0 900+900=10 1800+900=20 2700+900=30 3600+900=40");
+    q.id = 77;
+    assert!(series(&cm, &cs, &q).is_err());
+    // only numbers make a series, whatever the fill
+    q.id = 9;
+    for fill in [Fill::None, Fill::Null, Fill::Previous, Fill::Linear] {
+        q.fill = fill;
+        assert!(series(&cm, &cs, &q).is_err());
+    }
+}
@@ -0,0 +1,330 @@
+#![allow(dead_code)]
+
+use std::cmp::max;
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, Result};
+use bit_vec::BitVec;
+
+use crate::clutch::{ClutchKey, ClutchMeta, MemUsage, OmGroup, OmMeta, OmSink, OmType, OmValue, StoreMemUsage, RESIZE_INC};
+
+/*
+Slab storage mode.
+
+Instead of every clutch owning its own bit vectors and value arrays, all the values of
+a group live in a few big per-group vectors.  A clutch is just a row number in its
+group's slab, so a store with millions of clutches only does a handful of (amortized)
+allocations and clearing the values is a truncate.
+
+Rows are laid out row-major with a stride that is at least the group's slot count.
+When a group picks up more OM ids than the stride can hold the whole slab is re-laid
+out with a doubled stride - this is rare once a group has seen its OM set.
+*/
+
+#[derive(Debug, Default)]
+struct GroupSlab {
+    rows: usize,
+    stride32: usize,
+    stride64: usize,
+    om32: Vec<u32>,
+    om64: Vec<u64>,
+    set32: BitVec,
+    set64: BitVec,
+}
+
+fn next_stride(cur: usize, need: usize) -> usize {
+    let s = max(max(need, cur * 2), RESIZE_INC);
+    s.div_ceil(RESIZE_INC) * RESIZE_INC
+}
+
+fn restride<T: Copy + Default>(vals: &mut Vec<T>, set: &mut BitVec, rows: usize, old: usize, new: usize) {
+    let mut nvals = Vec::with_capacity(max(vals.capacity(), rows * new));
+    let mut nset = BitVec::with_capacity(rows * new);
+    for r in 0..rows {
+        nvals.extend_from_slice(&vals[r * old..(r + 1) * old]);
+        nvals.resize((r + 1) * new, T::default());
+        for s in 0..old {
+            nset.push(set[r * old + s]);
+        }
+        nset.grow(new - old, false);
+    }
+    *vals = nvals;
+    *set = nset;
+}
+
+impl GroupSlab {
+    fn ensure_stride(&mut self, slots32: usize, slots64: usize) {
+        if slots32 > self.stride32 {
+            let new = next_stride(self.stride32, slots32);
+            restride(&mut self.om32, &mut self.set32, self.rows, self.stride32, new);
+            self.stride32 = new;
+        }
+        if slots64 > self.stride64 {
+            let new = next_stride(self.stride64, slots64);
+            restride(&mut self.om64, &mut self.set64, self.rows, self.stride64, new);
+            self.stride64 = new;
+        }
+    }
+
+    fn add_row(&mut self, group: &OmGroup) -> usize {
+        self.ensure_stride(group.om32_slots, group.om64_slots);
+        let row = self.rows;
+        self.rows += 1;
+        self.om32.resize(self.rows * self.stride32, 0);
+        self.om64.resize(self.rows * self.stride64, 0);
+        self.set32.grow(self.stride32, false);
+        self.set64.grow(self.stride64, false);
+        row
+    }
+
+    fn clear(&mut self) {
+        // all Copy data so these are just length resets - the capacity is kept for reuse
+        self.rows = 0;
+        self.om32.clear();
+        self.om64.clear();
+        self.set32.truncate(0);
+        self.set64.truncate(0);
+    }
+
+    /// set values as value bytes, set bits as null bitmap bytes, the rest of the
+    /// capacity as wasted
+    fn memory_usage(&self) -> MemUsage {
+        let set = |b: &BitVec| b.blocks().map(|b| b.count_ones() as usize).sum::<usize>();
+        let used = set(&self.set32) * std::mem::size_of::<u32>() + set(&self.set64) * std::mem::size_of::<u64>();
+        MemUsage {
+            clutches: self.rows,
+            key_bytes: 0,
+            value_bytes: std::mem::size_of::<GroupSlab>() + used,
+            null_bitmap_bytes: (self.set32.capacity() + self.set64.capacity()) / 8,
+            meta_bytes: 0,
+            wasted_bytes: self.om32.capacity() * std::mem::size_of::<u32>()
+                + self.om64.capacity() * std::mem::size_of::<u64>() - used,
+        }
+    }
+
+    fn get_value(&self, row: usize, meta: &OmMeta) -> OmValue {
+        match meta.kind {
+            OmType::TypeU32 => {
+                if meta.slot < self.stride32 && self.set32[row * self.stride32 + meta.slot] {
+                    OmValue::U32(self.om32[row * self.stride32 + meta.slot])
+                } else {
+                    OmValue::Unset
+                }
+            }
+            OmType::TypeF64 => {
+                if meta.slot < self.stride64 && self.set64[row * self.stride64 + meta.slot] {
+                    OmValue::F64(f64::from_bits(self.om64[row * self.stride64 + meta.slot]))
+                } else {
+                    OmValue::Unset
+                }
+            }
+            _ => panic!("error in get value, kind not mapped"),
+        }
+    }
+}
+
+/// A single clutch (row) of a `SlabStore` open for writing.
+pub struct SlabClutch<'a> {
+    slab: &'a mut GroupSlab,
+    row: usize,
+}
+
+impl<'a> SlabClutch<'a> {
+    pub fn row(&self) -> usize {
+        self.row
+    }
+
+    pub fn get_value(&self, meta: &OmMeta) -> OmValue {
+        self.slab.get_value(self.row, meta)
+    }
+}
+
+impl<'a> OmSink for SlabClutch<'a> {
+    #[inline(always)]
+    fn add_om_u32(&mut self, overwrite: bool, group: &mut OmGroup, id: u32, val: u32) -> Result<()> {
+        let slot = group.find_setup_meta_slot(id, &OmType::TypeU32);
+        if slot >= self.slab.stride32 {
+            self.slab.ensure_stride(slot + 1, 0);
+        }
+        let i = self.row * self.slab.stride32 + slot;
+        if !overwrite && self.slab.set32[i] {
+            Err(anyhow!("duplicate u32 OM id: {} val: {}", id, val))
+        } else {
+            self.slab.set32.set(i, true);
+            self.slab.om32[i] = val;
+            Ok(())
+        }
+    }
+
+    #[inline(always)]
+    fn add_om_f64(&mut self, overwrite: bool, group: &mut OmGroup, id: u32, val: f64) -> Result<()> {
+        let slot = group.find_setup_meta_slot(id, &OmType::TypeF64);
+        if slot >= self.slab.stride64 {
+            self.slab.ensure_stride(0, slot + 1);
+        }
+        let i = self.row * self.slab.stride64 + slot;
+        if !overwrite && self.slab.set64[i] {
+            Err(anyhow!("duplicate f64 OM id: {} val: {}", id, val))
+        } else {
+            self.slab.set64.set(i, true);
+            self.slab.om64[i] = val.to_bits();
+            Ok(())
+        }
+    }
+
+    fn set_null(&mut self, _overwrite: bool, group: &mut OmGroup, id: u32, _kind: &OmType) -> Result<()> {
+        Err(anyhow!("slab store has no explicit NULLs - OM id: {} group: {}", id, &group.group))
+    }
+}
+
+/// Clutch store where OM values live in contiguous per-group slabs indexed by
+/// clutch number rather than in a `ClutchData` per clutch.
+#[derive(Debug, Default)]
+pub struct SlabStore {
+    index: BTreeMap<ClutchKey, usize>,
+    slabs: Vec<GroupSlab>,
+}
+
+impl SlabStore {
+    pub fn new() -> SlabStore {
+        SlabStore {
+            index: BTreeMap::new(),
+            slabs: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// Drops all clutches.  The slabs keep their capacity, so the value side of this is
+    /// O(1) per group no matter how many clutches were stored.
+    pub fn clear_oms(&mut self) {
+        self.index.clear();
+        for s in self.slabs.iter_mut() {
+            s.clear();
+        }
+    }
+
+    /// Memory held by group, counted as `ClutchStore::memory_usage` counts it.  Slabs keep
+    /// their capacity through `clear_oms`, which shows as wasted bytes.
+    pub fn memory_usage(&self, cm: &ClutchMeta) -> StoreMemUsage {
+        let mut mu = StoreMemUsage::default();
+        mu.groups.resize(max(cm.groups().count(), self.slabs.len()), MemUsage::default());
+        for (g, gm) in cm.groups().zip(mu.groups.iter_mut()) {
+            gm.meta_bytes = g.meta_bytes();
+        }
+        for (s, gm) in self.slabs.iter().zip(mu.groups.iter_mut()) {
+            gm.add(&s.memory_usage());
+        }
+        for ck in self.index.keys() {
+            mu.groups[ck.groupidx as usize].key_bytes += std::mem::size_of::<(ClutchKey, usize)>() + ck.keys().len();
+        }
+        for gm in &mu.groups {
+            mu.total.add(gm);
+        }
+        mu
+    }
+
+    pub fn find_or_add_clutch(&mut self, group: &OmGroup, key: &ClutchKey) -> SlabClutch<'_> {
+        let gi = key.groupidx as usize;
+        if self.slabs.len() <= gi {
+            self.slabs.resize_with(gi + 1, GroupSlab::default);
+        }
+        let slab = &mut self.slabs[gi];
+        let row = match self.index.get(key) {
+            Some(row) => *row,
+            None => {
+                let row = slab.add_row(group);
+                self.index.insert(key.clone(), row);
+                row
+            }
+        };
+        SlabClutch { slab, row }
+    }
+
+    pub fn get_value(&self, key: &ClutchKey, meta: &OmMeta) -> Option<OmValue> {
+        let row = self.index.get(key)?;
+        Some(self.slabs[key.groupidx as usize].get_value(*row, meta))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&ClutchKey, usize)> {
+        self.index.iter().map(|(k, r)| (k, *r))
+    }
+}
+
+pub fn dump(cm: &ClutchMeta, ss: &SlabStore, first_last: bool) {
+    let mut at = 0;
+    if ss.is_empty() { eprintln!("HEY no clutches here?"); }
+    for (ck, row) in ss.iter() {
+        at += 1;
+        if !first_last || at == 1 || at == ss.len() {
+            let g = cm.get_group(ck.groupidx).unwrap();
+            let slab = &ss.slabs[ck.groupidx as usize];
+            println!("{} {{ group: {} row: {} key: {}  time: {} dur: {}", at, &g.group, row,
+                     ck.keys().replace('\0', ", "), ck.start_iso(), ck.dur());
+            print!("\t{}",
+                   &g.om_map.iter().map(|x|
+                       format!("{}:{} {}", x.0, slab.get_value(row, x.1), &x.1.kind)).
+                       collect::<Vec<_>>().join(", "));
+            println!("}}");
+        }
+    }
+}
+
+#[test]
+fn test_slab_store() {
+    let mut cm = ClutchMeta::new();
+    let group = cm.find_or_new_group("slab");
+    let mut ss = SlabStore::new();
+
+    for k in 0..100u32 {
+        let key = ClutchKey::new(group.idx, k.to_string(), 1960, 900, 0);
+        let mut c = ss.find_or_add_clutch(group, &key);
+        assert_eq!(c.row(), k as usize);
+        // more ids than the first stride to force a re-layout along the way
+        for id in 0..(k % 20) {
+            c.add_om_u32(false, group, id, id + k).unwrap();
+            c.add_om_f64(false, group, 1000 + id, (id + k) as f64 + 0.5).unwrap();
+        }
+    }
+    let key = ClutchKey::new(group.idx, 5.to_string(), 1960, 900, 0);
+    assert!(ss.find_or_add_clutch(group, &key).add_om_u32(false, group, 1, 0).is_err());
+
+    for k in 0..100u32 {
+        let key = ClutchKey::new(group.idx, k.to_string(), 1960, 900, 0);
+        for id in 0..19 {
+            let v = ss.get_value(&key, group.om_map.get(&id).unwrap()).unwrap();
+            match v {
+                OmValue::U32(v) => { assert!(id < k % 20); assert_eq!(v, id + k); }
+                OmValue::Unset => assert!(id >= k % 20),
+                _ => panic!("unexpected value {}", v),
+            }
+            let v = ss.get_value(&key, group.om_map.get(&(1000 + id)).unwrap()).unwrap();
+            match v {
+                OmValue::F64(v) => assert_eq!(v, (id + k) as f64 + 0.5),
+                OmValue::Unset => assert!(id >= k % 20),
+                _ => panic!("unexpected value {}", v),
+            }
+        }
+    }
+
+    let gi = group.idx as usize;
+    let mu = ss.memory_usage(&cm);
+    assert_eq!((mu.total.clutches, mu.groups[gi].clutches), (100, 100));
+    assert!(mu.total.key_bytes > 0 && mu.total.value_bytes > 0 && mu.total.meta_bytes > 0);
+
+    ss.clear_oms();
+    assert!(ss.is_empty());
+    let mu = ss.memory_usage(&cm);
+    assert_eq!((mu.total.clutches, mu.total.key_bytes), (0, 0));
+    assert!(mu.total.wasted_bytes > 0);
+    let group = cm.get_group_by_name("slab").unwrap();
+    let key = ClutchKey::new(group.idx, "again".to_string(), 1960, 900, 0);
+    assert_eq!(ss.find_or_add_clutch(group, &key).row(), 0);
+    assert!(matches!(ss.get_value(&key, group.om_map.get(&3).unwrap()), Some(OmValue::Unset)));
+}
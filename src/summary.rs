@@ -0,0 +1,271 @@
+#![allow(dead_code)]
+
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, Result};
+
+use crate::clutch::{ClutchMeta, ClutchStore, OmType, OmValue};
+
+/*
+One pass summary statistics of an OM.
+
+count/sum/min/max are exact, mean and variance use Welford's update (and Chan's formula
+to merge), and quantiles come from a log bucketed sketch: a value v > 0 lands in bucket
+ceil(log_gamma(v)) with gamma = (1 + a) / (1 - a), so reading a bucket back as
+2 * gamma^i / (gamma + 1) is within relative error `a` of anything in it.  Buckets are
+just counts, so sketches with the same `a` merge by adding them - per thread or per
+shard summaries combine into exactly what one pass over everything would give.
+*/
+
+/// Relative accuracy of quantiles unless asked for otherwise.
+pub const DEFAULT_ACCURACY: f64 = 0.01;
+/// magnitudes below this are counted as zero
+const MIN_MAGNITUDE: f64 = 1e-12;
+
+#[derive(Debug, Clone)]
+pub struct Sketch {
+    accuracy: f64,
+    ln_gamma: f64,
+    pos: BTreeMap<i32, u64>,
+    neg: BTreeMap<i32, u64>,
+    zeros: u64,
+}
+
+impl Sketch {
+    /// `accuracy` must be strictly between 0 and 1.
+    pub fn new(accuracy: f64) -> Result<Sketch> {
+        if !(accuracy > 0.0 && accuracy < 1.0) {
+            return Err(anyhow!("quantile accuracy {} is not between 0 and 1", accuracy));
+        }
+        let gamma = (1.0 + accuracy) / (1.0 - accuracy);
+        Ok(Sketch { accuracy, ln_gamma: gamma.ln(), pos: BTreeMap::new(), neg: BTreeMap::new(), zeros: 0 })
+    }
+
+    fn index(&self, mag: f64) -> i32 {
+        (mag.ln() / self.ln_gamma).ceil() as i32
+    }
+
+    /// the value a bucket reads back as
+    fn value(&self, i: i32) -> f64 {
+        let gamma = self.ln_gamma.exp();
+        2.0 * (self.ln_gamma * i as f64).exp() / (gamma + 1.0)
+    }
+
+    pub fn add(&mut self, v: f64) {
+        if v.abs() < MIN_MAGNITUDE {
+            self.zeros += 1;
+        } else if v > 0.0 {
+            *self.pos.entry(self.index(v)).or_insert(0) += 1;
+        } else {
+            *self.neg.entry(self.index(-v)).or_insert(0) += 1;
+        }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.zeros + self.pos.values().sum::<u64>() + self.neg.values().sum::<u64>()
+    }
+
+    pub fn merge(&mut self, other: &Sketch) -> Result<()> {
+        if self.accuracy != other.accuracy {
+            return Err(anyhow!("sketches of accuracy {} and {} cannot be merged", self.accuracy, other.accuracy));
+        }
+        for (i, c) in &other.pos {
+            *self.pos.entry(*i).or_insert(0) += c;
+        }
+        for (i, c) in &other.neg {
+            *self.neg.entry(*i).or_insert(0) += c;
+        }
+        self.zeros += other.zeros;
+        Ok(())
+    }
+
+    /// (lower, upper, count) of every non-empty bucket from the smallest values up
+    pub fn buckets(&self) -> Vec<(f64, f64, u64)> {
+        let bounds = |i: i32| ((self.ln_gamma * (i - 1) as f64).exp(), (self.ln_gamma * i as f64).exp());
+        let mut v: Vec<(f64, f64, u64)> = self.neg.iter().rev()
+            .map(|(i, c)| { let (lo, hi) = bounds(*i); (-hi, -lo, *c) })
+            .collect();
+        if self.zeros > 0 {
+            v.push((0.0, 0.0, self.zeros));
+        }
+        v.extend(self.pos.iter().map(|(i, c)| { let (lo, hi) = bounds(*i); (lo, hi, *c) }));
+        v
+    }
+
+    /// Value at quantile `q` (0..=1), None when empty.
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+        let rank = (q.clamp(0.0, 1.0) * (count - 1) as f64).round() as u64;
+        let mut seen = 0;
+        for (i, c) in self.neg.iter().rev() {
+            seen += c;
+            if seen > rank {
+                return Some(-self.value(*i));
+            }
+        }
+        seen += self.zeros;
+        if seen > rank {
+            return Some(0.0);
+        }
+        for (i, c) in self.pos.iter() {
+            seen += c;
+            if seen > rank {
+                return Some(self.value(*i));
+            }
+        }
+        None
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OmStats {
+    pub count: u64,
+    pub sum: f64,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    mean: f64,
+    /// sum of squared differences from the mean
+    m2: f64,
+    pub sketch: Sketch,
+}
+
+impl OmStats {
+    pub fn new(accuracy: f64) -> Result<OmStats> {
+        Ok(OmStats { count: 0, sum: 0.0, min: None, max: None, mean: 0.0, m2: 0.0, sketch: Sketch::new(accuracy)? })
+    }
+
+    pub fn add(&mut self, v: f64) {
+        self.count += 1;
+        self.sum += v;
+        self.min = Some(self.min.map_or(v, |m| m.min(v)));
+        self.max = Some(self.max.map_or(v, |m| m.max(v)));
+        let d = v - self.mean;
+        self.mean += d / self.count as f64;
+        self.m2 += d * (v - self.mean);
+        self.sketch.add(v);
+    }
+
+    pub fn merge(&mut self, other: &OmStats) -> Result<()> {
+        self.sketch.merge(&other.sketch)?;
+        if other.count == 0 {
+            return Ok(());
+        }
+        let n = (self.count + other.count) as f64;
+        let d = other.mean - self.mean;
+        self.m2 += other.m2 + d * d * self.count as f64 * other.count as f64 / n;
+        self.mean += d * other.count as f64 / n;
+        self.count += other.count;
+        self.sum += other.sum;
+        self.min = match (self.min, other.min) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        self.max = match (self.max, other.max) {
+            (Some(a), Some(b)) => Some(a.max(b)),
+            (a, b) => a.or(b),
+        };
+        Ok(())
+    }
+
+    pub fn mean(&self) -> Option<f64> {
+        if self.count == 0 { None } else { Some(self.mean) }
+    }
+
+    /// sample standard deviation, None below two values
+    pub fn stddev(&self) -> Option<f64> {
+        if self.count < 2 { None } else { Some((self.m2 / (self.count - 1) as f64).sqrt()) }
+    }
+
+    /// Quantile from the sketch, kept within the exact min and max (which are what 0 and 1
+    /// give).
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        if q <= 0.0 {
+            return self.min;
+        } else if q >= 1.0 {
+            return self.max;
+        }
+        let v = self.sketch.quantile(q)?;
+        Some(v.clamp(self.min?, self.max?))
+    }
+}
+
+impl std::fmt::Display for OmStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let o = |v: Option<f64>| v.map_or("-".to_string(), |v| format!("{}", v));
+        write!(f, "count: {} sum: {} mean: {} stddev: {} min: {} max: {} p50: {} p90: {} p99: {}",
+               self.count, self.sum, o(self.mean()), o(self.stddev()), o(self.min), o(self.max),
+               o(self.quantile(0.5)), o(self.quantile(0.9)), o(self.quantile(0.99)))
+    }
+}
+
+/// Statistics of OM `id` over the clutches of `group` with `time` in `from..to`.  NULL,
+/// never reported and NaN values are left out.
+pub fn om_stats(cm: &ClutchMeta, cs: &ClutchStore, group: u16, id: u32, from: u64, to: u64, accuracy: f64) -> Result<OmStats> {
+    let g = cm.get_group(group).ok_or_else(|| anyhow!("no group with index {}", group))?;
+    let meta = g.om_map.get(&id).ok_or_else(|| anyhow!("OM id: {} not known in group {}", id, &g.group))?;
+    if !matches!(meta.kind, OmType::TypeU32 | OmType::TypeF64) {
+        return Err(anyhow!("OM id: {} of type {} has no statistics", id, &meta.kind));
+    }
+    let mut st = OmStats::new(accuracy)?;
+    let clutches = cs.iter().filter(|(ck, _)| ck.groupidx == group && ck.time() >= from && ck.time() < to);
+    for (_, cd) in clutches {
+        match cd.get_value(meta) {
+            OmValue::U32(v) => st.add(v as f64),
+            OmValue::F64(v) if !v.is_nan() => st.add(v),
+            _ => {}
+        }
+    }
+    Ok(st)
+}
+
+#[test]
+fn test_om_stats() {
+    use crate::clutch::ClutchKey;
+
+    let mut cm = ClutchMeta::new();
+    let mut cs = ClutchStore::new();
+    let g = cm.find_or_new_group("lat");
+    for k in 1..=10_000u32 {
+        let key = ClutchKey::new(g.idx, k.to_string(), (k as u64 % 2) * 900, 900, 0);
+        let cd = cs.find_or_add_clutchdata(g, &key);
+        cd.add_om_u32(false, g, 1, k).unwrap();
+        if k % 100 == 0 {
+            cd.set_null(false, g, 2, &OmType::TypeF64).unwrap();
+        } else {
+            cd.add_om_f64(false, g, 2, k as f64 - 5000.0).unwrap();
+        }
+    }
+
+    let gi = g.idx;
+    let st = om_stats(&cm, &cs, gi, 1, 0, u64::MAX, DEFAULT_ACCURACY).unwrap();
+    assert_eq!((st.count, st.sum, st.min, st.max), (10_000, 50_005_000.0, Some(1.0), Some(10_000.0)));
+    assert!((st.mean().unwrap() - 5000.5).abs() < 1e-9);
+    assert!((st.stddev().unwrap() - 2886.8957).abs() < 1e-3);
+    for (q, exact) in [(0.5, 5000.0), (0.9, 9000.0), (0.99, 9900.0)] {
+        let v = st.quantile(q).unwrap();
+        assert!((v - exact).abs() <= exact * DEFAULT_ACCURACY + 1.0, "p{} {} vs {}", q, v, exact);
+    }
+
+    // per period stats merge into the same as one pass
+    let mut odd = om_stats(&cm, &cs, gi, 2, 900, 901, DEFAULT_ACCURACY).unwrap();
+    let even = om_stats(&cm, &cs, gi, 2, 0, 900, DEFAULT_ACCURACY).unwrap();
+    let all = om_stats(&cm, &cs, gi, 2, 0, u64::MAX, DEFAULT_ACCURACY).unwrap();
+    assert_eq!((odd.count, even.count), (5000, 4900));
+    odd.merge(&even).unwrap();
+    assert_eq!((odd.count, odd.sum, odd.min, odd.max), (all.count, all.sum, all.min, all.max));
+    assert!((odd.stddev().unwrap() - all.stddev().unwrap()).abs() < 1e-6);
+    for q in [0.0, 0.5, 0.9, 0.99, 1.0] {
+        assert_eq!(odd.quantile(q), all.quantile(q));
+    }
+    assert_eq!(all.quantile(0.0), Some(-4999.0));
+    assert!(all.quantile(0.5).unwrap().abs() < 50.0 * DEFAULT_ACCURACY + 1.0);
+    assert_eq!(all.sketch.buckets().iter().map(|b| b.2).sum::<u64>(), 9900);
+    assert!(odd.merge(&OmStats::new(0.05).unwrap()).is_err());
+    for bad in [0.0, 1.0, -0.5, f64::NAN] {
+        assert!(om_stats(&cm, &cs, gi, 2, 0, u64::MAX, bad).is_err());
+    }
+    assert!(om_stats(&cm, &cs, gi, 3, 0, u64::MAX, DEFAULT_ACCURACY).is_err());
+}
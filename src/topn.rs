@@ -0,0 +1,143 @@
+#![allow(dead_code)]
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+
+use anyhow::{anyhow, Result};
+
+use crate::clutch::{ClutchKey, ClutchMeta, ClutchStore, OmType, OmValue};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rank {
+    /// largest values first
+    Top,
+    /// smallest values first
+    Bottom,
+}
+
+/// The `n` clutches of `group` with the largest (or smallest) value of OM `id` among those
+/// whose `time` is in `from..to`.
+#[derive(Debug, Clone)]
+pub struct TopNQuery {
+    pub group: u16,
+    pub id: u32,
+    pub from: u64,
+    pub to: u64,
+    pub n: usize,
+    pub rank: Rank,
+}
+
+/// heap entry ordered by value then key, so ties come out the same every time
+struct Entry<'a> {
+    val: f64,
+    key: &'a ClutchKey,
+}
+
+impl<'a> PartialEq for Entry<'a> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<'a> Eq for Entry<'a> {}
+
+impl<'a> PartialOrd for Entry<'a> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<'a> Ord for Entry<'a> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // smaller key ranks higher on ties, in both directions
+        self.val.total_cmp(&other.val).then_with(|| other.key.cmp(self.key))
+    }
+}
+
+/// Keeps the best `n` seen in a heap of at most `n`, with the weakest on top to be pushed
+/// out - O(clutches * log n) instead of sorting everything.
+fn push_bounded<T: Ord>(heap: &mut BinaryHeap<Reverse<T>>, n: usize, e: T) {
+    if heap.len() < n {
+        heap.push(Reverse(e));
+    } else if let Some(mut weakest) = heap.peek_mut() {
+        if e > weakest.0 {
+            *weakest = Reverse(e);
+        }
+    }
+}
+
+/// Runs `q`, returning keys and values best first.  Clutches without a value (NULL or
+/// never reported) and NaNs are not ranked.
+pub fn top_n(cm: &ClutchMeta, cs: &ClutchStore, q: &TopNQuery) -> Result<Vec<(ClutchKey, OmValue)>> {
+    let group = cm.get_group(q.group).ok_or_else(|| anyhow!("no group with index {}", q.group))?;
+    let meta = group.om_map.get(&q.id)
+        .ok_or_else(|| anyhow!("OM id: {} not known in group {}", q.id, &group.group))?;
+    if !matches!(meta.kind, OmType::TypeU32 | OmType::TypeF64) {
+        return Err(anyhow!("OM id: {} of type {} cannot be ranked", q.id, &meta.kind));
+    }
+    if q.n == 0 {
+        return Ok(vec![]);
+    }
+    let sign = if q.rank == Rank::Top { 1.0 } else { -1.0 };
+    let mut heap = BinaryHeap::with_capacity(q.n + 1);
+    let clutches = cs.iter()
+        .filter(|(ck, _)| ck.groupidx == q.group && ck.time() >= q.from && ck.time() < q.to);
+    for (ck, cd) in clutches {
+        let val = match cd.get_value(meta) {
+            OmValue::U32(v) => v as f64,
+            OmValue::F64(v) if !v.is_nan() => v,
+            _ => continue,
+        };
+        push_bounded(&mut heap, q.n, Entry { val: val * sign, key: ck });
+    }
+    Ok(heap.into_sorted_vec().into_iter()
+        .map(|Reverse(e)| {
+            let cd = cs.get(e.key).unwrap();
+            (e.key.clone(), cd.get_value(meta))
+        })
+        .collect())
+}
+
+#[test]
+fn test_top_n() {
+    let mut cm = ClutchMeta::new();
+    let mut cs = ClutchStore::new();
+    let g = cm.find_or_new_group("cells");
+    for t in [0u64, 3600] {
+        for k in 0..50u32 {
+            let key = ClutchKey::new(g.idx, format!("cell{:02}", k), t, 3600, 0);
+            let cd = cs.find_or_add_clutchdata(g, &key);
+            match k {
+                7 => cd.set_null(false, g, 1, &OmType::TypeF64).unwrap(),
+                8 => cd.add_om_f64(false, g, 1, f64::NAN).unwrap(),
+                // ties with cell25
+                12 => cd.add_om_f64(false, g, 1, 0.5).unwrap(),
+                _ => cd.add_om_f64(false, g, 1, ((k * 37) % 50) as f64 / 50.0 + t as f64).unwrap(),
+            }
+            cd.add_om_u32(false, g, 2, k).unwrap();
+        }
+    }
+    let mut q = TopNQuery { group: g.idx, id: 1, from: 0, to: 3600, n: 3, rank: Rank::Top };
+    let got = |cm: &ClutchMeta, q: &TopNQuery| top_n(cm, &cs, q).unwrap().iter()
+        .map(|(k, v)| format!("{}={}", k.keys(), v)).collect::<Vec<_>>();
+    assert_eq!(got(&cm, &q), vec!["cell27=0.98", "cell04=0.96", "cell31=0.94"]);
+    q.rank = Rank::Bottom;
+    q.n = 4;
+    assert_eq!(got(&cm, &q), vec!["cell00=0", "cell23=0.02", "cell46=0.04", "cell19=0.06"]);
+    // equal values rank by key
+    q.n = 100;
+    let all = got(&cm, &q);
+    let at = all.iter().position(|s| s.ends_with("=0.5")).unwrap();
+    assert_eq!(all[at..at + 2], ["cell12=0.5", "cell25=0.5"]);
+    assert_eq!(all.len(), 48);
+    q.n = 4;
+    q.id = 2;
+    q.from = 3600;
+    q.to = u64::MAX;
+    assert_eq!(got(&cm, &q), vec!["cell00=0", "cell01=1", "cell02=2", "cell03=3"]);
+    q.rank = Rank::Top;
+    q.n = 100;
+    assert_eq!(top_n(&cm, &cs, &q).unwrap().len(), 50);
+    q.id = 77;
+    assert!(top_n(&cm, &cs, &q).is_err());
+}